@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use iregex_automata::DFA;
+
+/// Minimal DFA of `[ab]*a[ab]{n}`, whose states are the last `n + 1`
+/// characters read, `a` being a set bit.
+fn nth_from_last(n: u32) -> DFA<u32> {
+	let mask = (1 << (n + 1)) - 1;
+	let mut dfa = DFA::new(0);
+	for q in 0..=mask {
+		dfa.add(q, ('a'..='a').into(), ((q << 1) | 1) & mask);
+		dfa.add(q, ('b'..='b').into(), (q << 1) & mask);
+		if q & (1 << n) != 0 {
+			dfa.add_final_state(q);
+		}
+	}
+	dfa
+}
+
+/// Pseudo-random input made of `a` and `b`.
+fn input(len: usize) -> String {
+	let mut seed: u32 = 1;
+	(0..len)
+		.map(|_| {
+			seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+			if seed & 0x10000 == 0 {
+				'a'
+			} else {
+				'b'
+			}
+		})
+		.collect()
+}
+
+fn dense_dfa(c: &mut Criterion) {
+	let dfa = nth_from_last(6);
+	let dense = dfa.compile_dense();
+	let table = dfa.to_table();
+	let input = input(64 * 1024);
+
+	let mut group = c.benchmark_group("dense_dfa");
+	group.throughput(Throughput::Bytes(input.len() as u64));
+	group.bench_function("dfa", |b| b.iter(|| dfa.accepts(black_box(&input))));
+	group.bench_function("dense_dfa", |b| b.iter(|| dense.accepts(black_box(&input))));
+	group.bench_function("dfa_table", |b| {
+		b.iter(|| table.is_match(black_box(&input)))
+	});
+	group.finish();
+}
+
+criterion_group!(benches, dense_dfa);
+criterion_main!(benches);
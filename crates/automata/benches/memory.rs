@@ -0,0 +1,74 @@
+//! Peak memory used by determinization, measured with a counting allocator.
+//!
+//! Run with `cargo bench --bench memory`.
+use std::{
+	alloc::{GlobalAlloc, Layout, System},
+	collections::BTreeSet,
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+use btree_range_map::RangeSet;
+use iregex_automata::NFA;
+
+/// Allocator keeping track of the current and peak number of allocated
+/// bytes.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let ptr = System.alloc(layout);
+		if !ptr.is_null() {
+			let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+			PEAK.fetch_max(current, Ordering::Relaxed);
+		}
+		ptr
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout);
+		CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+	}
+}
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc;
+
+/// `[ab]*a[ab]{n}`, whose minimal DFA has `2^(n + 1)` states.
+fn nth_from_last(n: u32) -> NFA {
+	let ab: RangeSet<char> = ['a', 'b'].into_iter().collect();
+	let mut nfa = NFA::new();
+	nfa.add(0, Some(ab.clone()), 0);
+	nfa.add(0, Some(['a'].into_iter().collect()), 1);
+	for q in 1..=n {
+		nfa.add(q, Some(ab.clone()), q + 1);
+	}
+	nfa.add_initial_state(0);
+	nfa.add_final_state(n + 1);
+	nfa
+}
+
+fn main() {
+	for n in [8, 12, 16] {
+		let nfa = nth_from_last(n);
+
+		let base = CURRENT.load(Ordering::Relaxed);
+		PEAK.store(base, Ordering::Relaxed);
+
+		// subsets of at most 64 states are mapped to their bit mask.
+		let dfa = nfa.determinize(|det_q: &BTreeSet<&u32>| {
+			det_q.iter().fold(0u64, |mask, q| mask | 1 << **q)
+		});
+		let peak = PEAK.load(Ordering::Relaxed) - base;
+		let result = CURRENT.load(Ordering::Relaxed) - base;
+
+		println!(
+			"determinize [ab]*a[ab]{{{n}}}: {} DFA states, peak {} KiB, result {} KiB",
+			dfa.transitions().len(),
+			peak / 1024,
+			result / 1024
+		);
+	}
+}
@@ -0,0 +1,237 @@
+use std::{
+	collections::{BTreeSet, HashSet},
+	hash::{BuildHasherDefault, Hasher},
+};
+
+use btree_range_map::RangeSet;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use iregex_automata::{charset_intersection, DynMatcher, NFA};
+
+/// `[ab]*c`, where every loop goes through a long chain of
+/// epsilon-transitions.
+fn epsilon_chain(len: u32) -> NFA {
+	let mut nfa = NFA::new();
+	for q in 0..len {
+		nfa.add(q, None, q + 1);
+	}
+	nfa.add(len, Some(['a', 'b'].into_iter().collect()), 0);
+	nfa.add(len, Some(['c'].into_iter().collect()), len + 1);
+	nfa.add_initial_state(0);
+	nfa.add_final_state(len + 1);
+	nfa
+}
+
+fn epsilon_closures(c: &mut Criterion) {
+	let nfa = epsilon_chain(100);
+	let closed = nfa.with_epsilon_closures();
+	let input = "ab".repeat(500) + "c";
+
+	let mut group = c.benchmark_group("epsilon_closures");
+	group.bench_function("nfa", |b| {
+		b.iter(|| DynMatcher::is_match(&nfa, black_box(&input)))
+	});
+	group.bench_function("closed_nfa", |b| {
+		b.iter(|| DynMatcher::is_match(&closed, black_box(&input)))
+	});
+	group.finish();
+}
+
+/// `[ab]*a[ab]{n}`, whose minimal DFA has `2^(n + 1)` states.
+fn nth_from_last(n: u32) -> NFA {
+	let ab: RangeSet<char> = ['a', 'b'].into_iter().collect();
+	let mut nfa = NFA::new();
+	nfa.add(0, Some(ab.clone()), 0);
+	nfa.add(0, Some(['a'].into_iter().collect()), 1);
+	for q in 1..=n {
+		nfa.add(q, Some(ab.clone()), q + 1);
+	}
+	nfa.add_initial_state(0);
+	nfa.add_final_state(n + 1);
+	nfa
+}
+
+fn compile_table(c: &mut Criterion) {
+	let nfa = nth_from_last(10);
+
+	let mut group = c.benchmark_group("compile_table");
+	group.bench_function("compile_table", |b| {
+		b.iter(|| black_box(&nfa).compile_table(usize::MAX).unwrap())
+	});
+	group.bench_function("determinize_to_table", |b| {
+		b.iter(|| black_box(&nfa).determinize_with_mapping().0.to_table())
+	});
+	group.finish();
+}
+
+/// Chain of `len` states, each one going to the next through `width`
+/// distinct single-character labels, starting at `offset`.
+fn wide_chain(len: u32, width: u32, offset: u32) -> NFA {
+	let mut nfa = NFA::new();
+	for q in 0..len {
+		for c in 0..width {
+			let c = char::from_u32('a' as u32 + offset + c).unwrap();
+			nfa.add(q, Some([c].into_iter().collect()), q + 1);
+		}
+	}
+	nfa.add_initial_state(0);
+	nfa.add_final_state(len);
+	nfa
+}
+
+/// Product intersecting every pair of labels, as `NFA::product` did before
+/// sweeping the labels.
+fn naive_product(a: &NFA, b: &NFA) -> NFA<(u32, u32)> {
+	let mut result = NFA::new();
+
+	let mut stack = Vec::new();
+	for qa in a.initial_states() {
+		for qb in b.initial_states() {
+			stack.push((*qa, *qb));
+			result.add_initial_state((*qa, *qb));
+		}
+	}
+
+	let mut visited = HashSet::new();
+	while let Some((qa, qb)) = stack.pop() {
+		if visited.insert((qa, qb)) {
+			if a.is_final_state(&qa) && b.is_final_state(&qb) {
+				result.add_final_state((qa, qb));
+			}
+
+			for (a_label, a_successors) in a.successors(&qa) {
+				for (b_label, b_successors) in b.successors(&qb) {
+					let label = match (a_label, b_label) {
+						(Some(a_label), Some(b_label)) => {
+							let label = charset_intersection(a_label, b_label);
+							if label.is_empty() {
+								continue;
+							}
+
+							Some(label)
+						}
+						(None, None) => None,
+						_ => continue,
+					};
+
+					for sa in a_successors {
+						for sb in b_successors {
+							stack.push((*sa, *sb));
+							result.add((qa, qb), label.clone(), (*sa, *sb));
+						}
+					}
+				}
+			}
+		}
+	}
+
+	result
+}
+
+fn product(c: &mut Criterion) {
+	let a = wide_chain(20, 200, 0);
+	let b = wide_chain(20, 200, 100);
+
+	let mut group = c.benchmark_group("product");
+	group.bench_function("sweep", |bench| {
+		bench.iter(|| black_box(&a).product(black_box(&b), |qa, qb| (*qa, *qb)))
+	});
+	group.bench_function("naive", |bench| {
+		bench.iter(|| naive_product(black_box(&a), black_box(&b)))
+	});
+	group.finish();
+}
+
+type Edges = Vec<(Option<RangeSet<char>>, u32)>;
+
+/// Epsilon-transitions from `0` to `width` new states, then transitions from
+/// `0` to the same states through single-character labels, to be added to an
+/// automaton already having `len` states.
+fn fan_out_edges(len: u32, width: u32) -> (NFA, Edges) {
+	let mut nfa = NFA::new();
+	for q in 0..len {
+		nfa.add_state(q)
+	}
+
+	let targets = len..len + width;
+	let edges = targets
+		.clone()
+		.map(|r| (None, r))
+		.chain(
+			targets
+				.zip('a'..)
+				.map(|(r, c)| (Some([c].into_iter().collect()), r)),
+		)
+		.collect();
+
+	(nfa, edges)
+}
+
+fn fan_out(c: &mut Criterion) {
+	let mut group = c.benchmark_group("fan_out");
+	group.bench_function("add", |b| {
+		b.iter_batched(
+			|| fan_out_edges(100_000, 1_000),
+			|(mut nfa, edges)| {
+				for (label, r) in edges {
+					nfa.add(0, label, r)
+				}
+				nfa
+			},
+			BatchSize::LargeInput,
+		)
+	});
+	group.bench_function("add_many", |b| {
+		b.iter_batched(
+			|| fan_out_edges(100_000, 1_000),
+			|(mut nfa, edges)| {
+				nfa.add_many(0, edges);
+				nfa
+			},
+			BatchSize::LargeInput,
+		)
+	});
+	group.finish();
+}
+
+/// FNV-1a hasher, much faster than the default SipHash on small keys.
+struct Fnv(u64);
+
+impl Default for Fnv {
+	fn default() -> Self {
+		Self(0xcbf29ce484222325)
+	}
+}
+
+impl Hasher for Fnv {
+	fn write(&mut self, bytes: &[u8]) {
+		for b in bytes {
+			self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3)
+		}
+	}
+
+	fn finish(&self) -> u64 {
+		self.0
+	}
+}
+
+fn determinize_with_hasher(c: &mut Criterion) {
+	let nfa = nth_from_last(10);
+	let f = |det_q: &BTreeSet<&u32>| det_q.iter().copied().copied().collect::<Vec<_>>();
+
+	let mut group = c.benchmark_group("determinize_with_hasher");
+	group.bench_function("sip", |b| b.iter(|| black_box(&nfa).determinize(f)));
+	group.bench_function("fnv", |b| {
+		b.iter(|| black_box(&nfa).determinize_with_hasher(f, BuildHasherDefault::<Fnv>::default()))
+	});
+	group.finish();
+}
+
+criterion_group!(
+	benches,
+	epsilon_closures,
+	compile_table,
+	product,
+	fan_out,
+	determinize_with_hasher
+);
+criterion_main!(benches);
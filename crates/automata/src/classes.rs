@@ -0,0 +1,361 @@
+//! Common character classes.
+//!
+//! The ASCII classes correspond to the `\d`, `\w` and `\s` escapes of most
+//! regular expression dialects. The `unicode_*` variants are computed from the
+//! Unicode tables of the `regex-syntax` crate, and require the `unicode`
+//! feature. POSIX bracket expression classes (such as `[:alpha:]`) are
+//! provided by [`posix`].
+//! Arbitrary sets can be written with the bracket-like syntax of
+//! [`char_class`].
+#[cfg(feature = "unicode")]
+use std::sync::{Mutex, PoisonError};
+use std::{iter::Peekable, str::Chars};
+
+use btree_range_map::RangeSet;
+
+/// Returns the set of characters of the given Unicode class expression,
+/// using the Unicode tables of [`regex_syntax`].
+///
+/// The result is computed once and cached in `cache`.
+#[cfg(feature = "unicode")]
+fn unicode_class(cache: &Mutex<Option<RangeSet<char>>>, pattern: &str) -> RangeSet<char> {
+	use regex_syntax::hir::{Class, HirKind};
+
+	let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+	cache
+		.get_or_insert_with(
+			|| match regex_syntax::parse(pattern).map(|hir| hir.into_kind()) {
+				Ok(HirKind::Class(Class::Unicode(class))) => class
+					.iter()
+					.map(|range| range.start()..=range.end())
+					.collect(),
+				_ => unreachable!("invalid Unicode class `{pattern}`"),
+			},
+		)
+		.clone()
+}
+
+/// ASCII digits (`\d`): `[0-9]`.
+pub fn digit() -> RangeSet<char> {
+	let mut result = RangeSet::new();
+	result.insert('0'..='9');
+	result
+}
+
+/// ASCII word characters (`\w`): `[0-9A-Za-z_]`.
+pub fn word() -> RangeSet<char> {
+	let mut result = digit();
+	result.insert('A'..='Z');
+	result.insert('a'..='z');
+	result.insert('_');
+	result
+}
+
+/// ASCII whitespace characters (`\s`): `[\t\n\x0b\x0c\r ]`.
+pub fn whitespace() -> RangeSet<char> {
+	let mut result = RangeSet::new();
+	result.insert('\t'..='\r');
+	result.insert(' ');
+	result
+}
+
+/// Unicode decimal digits (`\d`): the `Nd` general category.
+#[cfg(feature = "unicode")]
+pub fn unicode_digit() -> RangeSet<char> {
+	static CACHE: Mutex<Option<RangeSet<char>>> = Mutex::new(None);
+	unicode_class(&CACHE, r"\p{Nd}")
+}
+
+/// Unicode word characters (`\w`), as defined by [UTS #18]: alphabetic
+/// characters, marks, decimal digits, connector punctuation and join
+/// controls.
+///
+/// [UTS #18]: <https://unicode.org/reports/tr18/#word>
+#[cfg(feature = "unicode")]
+pub fn unicode_word() -> RangeSet<char> {
+	static CACHE: Mutex<Option<RangeSet<char>>> = Mutex::new(None);
+	unicode_class(&CACHE, r"\w")
+}
+
+/// Unicode whitespace characters (`\s`): the `White_Space` property.
+#[cfg(feature = "unicode")]
+pub fn unicode_whitespace() -> RangeSet<char> {
+	static CACHE: Mutex<Option<RangeSet<char>>> = Mutex::new(None);
+	unicode_class(&CACHE, r"\s")
+}
+
+/// Expands the given character set to include every character equivalent up
+/// to simple case folding.
+///
+/// For instance, folding `k` gives `k`, `K` and the Kelvin sign `K`
+/// (U+212A). Case folding uses the precomputed Unicode tables of
+/// [`regex_syntax`], so the cost is proportional to the size of the set
+/// rather than to the number of characters. Mappings to more than one
+/// character (such as `ß` to `SS`) are ignored.
+#[cfg(feature = "unicode")]
+pub fn case_fold(set: &RangeSet<char>) -> RangeSet<char> {
+	use regex_syntax::hir::{ClassUnicode, ClassUnicodeRange};
+
+	let mut class = ClassUnicode::new(
+		set.iter()
+			.filter_map(|range| Some(ClassUnicodeRange::new(range.first()?, range.last()?))),
+	);
+	class.case_fold_simple();
+
+	class
+		.iter()
+		.map(|range| range.start()..=range.end())
+		.collect()
+}
+
+/// Returns the POSIX character class with the given name (such as `alpha`
+/// for `[:alpha:]`), as defined in the POSIX locale.
+///
+/// | Name     | Characters              |
+/// |----------|-------------------------|
+/// | `upper`  | `[A-Z]`                 |
+/// | `lower`  | `[a-z]`                 |
+/// | `alpha`  | `[A-Za-z]`              |
+/// | `digit`  | `[0-9]`                 |
+/// | `alnum`  | `[0-9A-Za-z]`           |
+/// | `xdigit` | `[0-9A-Fa-f]`           |
+/// | `punct`  | ``[!-/:-@[-`{-~]``      |
+/// | `blank`  | `[\t ]`                 |
+/// | `space`  | `[\t\n\x0b\x0c\r ]`     |
+/// | `cntrl`  | `[\x00-\x1f\x7f]`       |
+/// | `graph`  | `[!-~]`                 |
+/// | `print`  | `[ -~]`                 |
+///
+/// Returns `None` if the name is not a POSIX class name.
+#[doc(alias = "posix_class")]
+pub fn posix(name: &str) -> Option<RangeSet<char>> {
+	let ranges: &[(char, char)] = match name {
+		"upper" => &[('A', 'Z')],
+		"lower" => &[('a', 'z')],
+		"alpha" => &[('A', 'Z'), ('a', 'z')],
+		"digit" => &[('0', '9')],
+		"alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+		"xdigit" => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+		"punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+		"blank" => &[('\t', '\t'), (' ', ' ')],
+		"space" => &[('\t', '\r'), (' ', ' ')],
+		"cntrl" => &[('\0', '\x1f'), ('\x7f', '\x7f')],
+		"graph" => &[('!', '~')],
+		"print" => &[(' ', '~')],
+		_ => return None,
+	};
+
+	let mut result = RangeSet::new();
+	for &(a, b) in ranges {
+		result.insert(a..=b)
+	}
+
+	Some(result)
+}
+
+/// Character class parse error, returned by [`char_class`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+	/// The specification ends with an unescaped `\`.
+	#[error("unexpected end of class after `\\`")]
+	TrailingBackslash,
+
+	/// A range whose first character is greater than its last.
+	#[error("invalid range `{0}-{1}`")]
+	InvalidRange(char, char),
+}
+
+/// Reads the next, possibly escaped, character of a class specification.
+fn next_class_char(chars: &mut Peekable<Chars>) -> Result<Option<char>, ParseError> {
+	match chars.next() {
+		Some('\\') => chars.next().map(Some).ok_or(ParseError::TrailingBackslash),
+		c => Ok(c),
+	}
+}
+
+/// Parses a character set from the content of a bracket expression, without
+/// the brackets.
+///
+/// The specification is a sequence of characters and ranges `a-z`. A leading
+/// `^` complements the set against [`any_char`](crate::any_char). A `-` that
+/// cannot be part of a range (at the start or end of the specification, or
+/// right after a range) is a literal hyphen. Any character can be escaped with
+/// `\`, so `\-`, `\^` and `\\` denote a literal hyphen, caret and
+/// backslash.
+///
+/// ```
+/// use iregex_automata::classes::char_class;
+///
+/// let set = char_class("a-z0-9_").unwrap();
+/// assert!(set.contains('q') && set.contains('_'));
+///
+/// let set = char_class("^a\\-z").unwrap();
+/// assert!(!set.contains('-') && set.contains('b'));
+/// ```
+pub fn char_class(spec: &str) -> Result<RangeSet<char>, ParseError> {
+	let mut chars = spec.chars().peekable();
+	let negated = chars.next_if_eq(&'^').is_some();
+
+	let mut result = RangeSet::new();
+	while let Some(a) = next_class_char(&mut chars)? {
+		let mut lookahead = chars.clone();
+		if lookahead.next() == Some('-') && lookahead.peek().is_some() {
+			chars = lookahead;
+			let b = next_class_char(&mut chars)?.unwrap();
+			if a > b {
+				return Err(ParseError::InvalidRange(a, b));
+			}
+
+			result.insert(a..=b)
+		} else {
+			result.insert(a)
+		}
+	}
+
+	if negated {
+		let mut complement = crate::any_char();
+		for range in result.iter() {
+			complement.remove(*range)
+		}
+
+		result = complement
+	}
+
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn digit() {
+		let set = super::digit();
+		assert!(('0'..='9').all(|c| set.contains(c)));
+		assert!(!set.contains('a'));
+		assert!(!set.contains('٣'));
+	}
+
+	#[cfg(feature = "unicode")]
+	#[test]
+	fn unicode_digit() {
+		let set = super::unicode_digit();
+		assert!(('0'..='9').all(|c| set.contains(c)));
+		assert!(!set.contains('a'));
+		assert!(set.contains('٣'));
+
+		// numeric characters that are not decimal digits.
+		for c in ['½', 'Ⅻ', '²', '①'] {
+			assert!(!set.contains(c), "{c:?}")
+		}
+
+		assert_eq!(super::unicode_digit(), set);
+	}
+
+	#[test]
+	fn word_and_whitespace() {
+		assert!(word().contains('_'));
+		assert!(!word().contains('-'));
+		assert!(whitespace().contains('\n'));
+	}
+
+	#[cfg(feature = "unicode")]
+	#[test]
+	fn unicode_word_and_whitespace() {
+		assert!(unicode_word().contains('é'));
+		assert!(unicode_word().contains('_'));
+		assert!(!unicode_word().contains('-'));
+		assert!(unicode_whitespace().contains('\u{a0}'));
+		assert!(!unicode_whitespace().contains('a'));
+	}
+
+	#[test]
+	fn posix() {
+		assert_eq!(super::posix("digit"), Some(super::digit()));
+		assert_eq!(super::posix("space"), Some(whitespace()));
+
+		let ascii = '\0'..='\x7f';
+		let vectors = [
+			("upper", char::is_ascii_uppercase as fn(&char) -> bool),
+			("lower", char::is_ascii_lowercase),
+			("alpha", char::is_ascii_alphabetic),
+			("digit", char::is_ascii_digit),
+			("alnum", char::is_ascii_alphanumeric),
+			("xdigit", char::is_ascii_hexdigit),
+			("punct", char::is_ascii_punctuation),
+			("blank", |c| *c == ' ' || *c == '\t'),
+			("space", |c| c.is_ascii_whitespace() || *c == '\x0b'),
+			("cntrl", char::is_ascii_control),
+			("graph", char::is_ascii_graphic),
+			("print", |c| c.is_ascii_graphic() || *c == ' '),
+		];
+
+		for (name, expected) in vectors {
+			let set = super::posix(name).unwrap();
+			for c in ascii.clone() {
+				assert_eq!(set.contains(c), expected(&c), "`{name}` class on {c:?}")
+			}
+			assert!(!set.contains('é'))
+		}
+
+		let alpha = super::posix("alpha").unwrap();
+		assert!(alpha.contains('q') && alpha.contains('Q'));
+		assert!(!alpha.contains('1'));
+
+		let punct = super::posix("punct").unwrap();
+		assert!(punct.contains('[') && punct.contains('~'));
+		assert!(!punct.contains(' '));
+
+		assert_eq!(super::posix("alphanum"), None);
+	}
+
+	#[test]
+	fn char_class() {
+		let set = super::char_class("a-z0-9_").unwrap();
+		assert_eq!(set, {
+			let mut expected = word();
+			expected.remove('A'..='Z');
+			expected
+		});
+
+		let set = super::char_class("^a-z").unwrap();
+		assert!(!set.contains('a') && !set.contains('z'));
+		assert!(set.contains('A') && set.contains('\u{10ffff}'));
+
+		let set = super::char_class("a\\-z").unwrap();
+		assert_eq!(set, RangeSet::from_iter(['a', '-', 'z']));
+
+		let set = super::char_class("-a-c-").unwrap();
+		assert_eq!(set, RangeSet::from_iter(['-'..='-', 'a'..='c']));
+
+		let set = super::char_class("\\\\\\^^").unwrap();
+		assert_eq!(set, RangeSet::from_iter(['\\', '^']));
+
+		assert_eq!(super::char_class(""), Ok(RangeSet::new()));
+		assert_eq!(super::char_class("^"), Ok(crate::any_char()));
+		assert_eq!(
+			super::char_class("z-a"),
+			Err(ParseError::InvalidRange('z', 'a'))
+		);
+		assert_eq!(super::char_class("a\\"), Err(ParseError::TrailingBackslash));
+	}
+
+	#[cfg(feature = "unicode")]
+	#[test]
+	fn case_fold() {
+		let set = super::case_fold(&RangeSet::from_iter(['k']));
+		assert!(set.contains('k'));
+		assert!(set.contains('K'));
+		assert!(set.contains('\u{212a}'));
+		assert!(!set.contains('l'));
+
+		let set = super::case_fold(&RangeSet::from_iter(['Σ']));
+		assert!(set.contains('σ'));
+		assert!(set.contains('ς'));
+
+		let set = super::case_fold(&RangeSet::from_iter(['a'..='z']));
+		assert!(set.contains('A') && set.contains('Z'));
+		assert!(set.contains('\u{17f}'));
+		assert!(!set.contains('0'));
+	}
+}
@@ -1,9 +1,18 @@
 use std::{
-	collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+	collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
 	hash::Hash,
+	ops::{Bound, Range},
 };
 
-use btree_range_map::AnyRange;
+use btree_range_map::{AnyRange, RangeSet};
+
+use crate::{
+	any_char, charset_intersection,
+	table::{DenseDfa, DfaTable, FlatDfa, FlatDfaError},
+	Automaton, DynMatcher, NFA,
+};
+
+pub use crate::matcher::MatchMode;
 
 /// Deterministic finite automaton.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -24,6 +33,13 @@ impl<Q, L> DFA<Q, L> {
 	}
 
 	/// Creates a new DFA from its internal representation.
+	///
+	/// The transitions leaving each state are expected to have disjoint
+	/// labels, otherwise the automaton is not deterministic. The initial and
+	/// final states are not required to appear in `transitions`: a state
+	/// without transitions simply rejects any further input.
+	///
+	/// This is the inverse of [`Self::into_parts`].
 	pub fn from_parts(
 		initial_state: Q,
 		final_states: BTreeSet<Q>,
@@ -36,6 +52,14 @@ impl<Q, L> DFA<Q, L> {
 		}
 	}
 
+	/// Deconstructs the DFA into its internal representation: the initial
+	/// state, final states and transitions.
+	///
+	/// This is the inverse of [`Self::from_parts`].
+	pub fn into_parts(self) -> (Q, BTreeSet<Q>, DetTransitions<Q, L>) {
+		(self.initial_state, self.final_states, self.transitions)
+	}
+
 	/// Returns the initial state of the automaton.
 	pub fn initial_state(&self) -> &Q {
 		&self.initial_state
@@ -71,6 +95,12 @@ impl<Q: Ord, L: Ord> DFA<Q, L> {
 		self.final_states.insert(q)
 	}
 
+	/// Checks if this automaton accepts the empty string, meaning that its
+	/// initial state is final.
+	pub fn accepts_empty(&self) -> bool {
+		self.is_final_state(&self.initial_state)
+	}
+
 	pub fn declare_state(&mut self, q: Q) {
 		self.transitions.0.entry(q).or_default();
 	}
@@ -79,10 +109,28 @@ impl<Q: Ord, L: Ord> DFA<Q, L> {
 		self.transitions.0.get(q).into_iter().flatten()
 	}
 
-	pub fn successors(&self, q: &Q) -> DetSuccessors<Q, L> {
+	pub fn successors(&self, q: &Q) -> DetSuccessors<'_, Q, L> {
 		DetSuccessors::new(self.transitions.0.get(q))
 	}
 
+	/// Builds the reverse index of the transitions: for each state, the
+	/// `(label, source)` pairs of its incoming transitions.
+	///
+	/// States without incoming transitions are absent from the index. The
+	/// index is recomputed on each call, callers performing several backward
+	/// analyses should cache it.
+	pub fn predecessors(&self) -> BTreeMap<&Q, Vec<(&L, &Q)>> {
+		let mut result: BTreeMap<_, Vec<_>> = BTreeMap::new();
+
+		for (q, q_transitions) in &self.transitions.0 {
+			for (label, r) in q_transitions {
+				result.entry(r).or_default().push((label, q))
+			}
+		}
+
+		result
+	}
+
 	pub fn add(&mut self, source: Q, label: L, target: Q) {
 		self.transitions
 			.0
@@ -182,9 +230,15 @@ impl<Q: Ord, L: Ord> DFA<Q, L> {
 	}
 
 	/// Minimizes the automaton.
+	///
+	/// The `partition` is the initial partition of the states to refine,
+	/// typically separating final states from non-final states (see
+	/// [`Self::partition`]). A final and a non-final state must never be in
+	/// the same initial class. States absent from the partition, such as
+	/// unreachable states, are removed from the result.
 	// Hopcroft's algorithm.
 	// https://en.wikipedia.org/wiki/DFA_minimization
-	pub fn minimize<'a, P>(&'a self, partition: P) -> DFA<BTreeSet<&Q>, &L>
+	pub fn minimize<'a, P>(&'a self, partition: P) -> DFA<BTreeSet<&'a Q>, &'a L>
 	where
 		Q: Hash,
 		L: Hash,
@@ -247,8 +301,16 @@ impl<Q: Ord, L: Ord> DFA<Q, L> {
 
 		let mut result = DFA::new(map[&self.initial_state].clone());
 		for (source, transitions) in &self.transitions.0 {
-			for (range, target) in transitions {
-				result.add(map[source].clone(), range, map[target].clone());
+			if let Some(source) = map.get(source) {
+				for (range, target) in transitions {
+					result.add(source.clone(), range, map[target].clone());
+				}
+			}
+		}
+
+		for q in &self.final_states {
+			if let Some(q) = map.get(q) {
+				result.add_final_state(q.clone());
 			}
 		}
 
@@ -373,6 +435,94 @@ impl<Q: Ord, L: Ord> DFA<Q, L> {
 		result
 	}
 
+	/// Checks if the given state is a sink (or trap) state: a non-final,
+	/// non-initial state whose transitions all loop back to itself.
+	fn is_sink_state(&self, q: &Q) -> bool {
+		!self.is_final_state(q)
+			&& !self.is_initial_state(q)
+			&& self.transitions.0.get(q).map_or(false, |q_transitions| {
+				q_transitions.values().all(|r| r == q)
+			})
+	}
+
+	/// Removes the sink states of the automaton, along with every transition
+	/// leading to them.
+	///
+	/// This is the inverse of completing the automaton: the resulting DFA is
+	/// incomplete, a missing transition meaning rejection. Matching is faster
+	/// on such trap-free automaton since failing characters do not need to be
+	/// looked up.
+	pub fn remove_sink(&self) -> DFA<Q, L>
+	where
+		Q: Clone,
+		L: Clone,
+	{
+		let sinks: BTreeSet<&Q> = self
+			.transitions
+			.0
+			.keys()
+			.filter(|q| self.is_sink_state(q))
+			.collect();
+
+		let mut transitions = BTreeMap::new();
+		for (source, q_transitions) in &self.transitions.0 {
+			if !sinks.contains(source) {
+				let q_transitions: BTreeMap<L, Q> = q_transitions
+					.iter()
+					.filter(|(_, target)| !sinks.contains(target))
+					.map(|(label, target)| (label.clone(), target.clone()))
+					.collect();
+
+				if !q_transitions.is_empty() {
+					transitions.insert(source.clone(), q_transitions);
+				}
+			}
+		}
+
+		DFA::from_parts(
+			self.initial_state.clone(),
+			self.final_states.clone(),
+			transitions.into(),
+		)
+	}
+
+	/// Returns the states from which a final state is reachable, including
+	/// the final states themselves.
+	fn co_reachable_states(&self) -> BTreeSet<&Q> {
+		let predecessors = self.predecessors();
+
+		let mut result: BTreeSet<&Q> = self.final_states.iter().collect();
+		let mut stack: Vec<&Q> = result.iter().copied().collect();
+		while let Some(q) = stack.pop() {
+			for (_, p) in predecessors.get(q).into_iter().flatten() {
+				if result.insert(p) {
+					stack.push(p)
+				}
+			}
+		}
+
+		result
+	}
+
+	/// Builds an automaton recognizing every prefix of the strings recognized
+	/// by this automaton.
+	///
+	/// Every state from which a final state is reachable is made final, so
+	/// that the result accepts exactly the inputs that can still be extended
+	/// into a recognized string. This is useful to check that a partial input
+	/// (for instance typed by a user) is still valid.
+	pub fn prefix_automaton(&self) -> DFA<Q, L>
+	where
+		Q: Clone,
+		L: Clone,
+	{
+		DFA::from_parts(
+			self.initial_state.clone(),
+			self.co_reachable_states().into_iter().cloned().collect(),
+			self.transitions.clone(),
+		)
+	}
+
 	/// Returns the single transition that follows the state `q`.
 	///
 	/// Returns `None` if the state has no transitions, or multiple transitions.
@@ -391,7 +541,7 @@ impl<Q: Ord, L: Ord> DFA<Q, L> {
 	/// # Example
 	///
 	/// ```
-	/// # use ere_automata::DFA;
+	/// # use iregex_automata::DFA;
 	/// # let dfa = DFA::new(0);
 	/// let _: DFA<_, String> = dfa.compress(|s: &mut String, c: &char| s.push(*c));
 	/// ```
@@ -435,83 +585,2193 @@ impl<Q: Ord, L: Ord> DFA<Q, L> {
 	}
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct DetTransitions<Q, L>(BTreeMap<Q, BTreeMap<L, Q>>);
+impl<Q: Ord> DFA<Q> {
+	/// Creates a new DFA from its internal representation, checking that the
+	/// transitions leaving each state have disjoint labels.
+	///
+	/// See [`Self::from_parts`].
+	pub fn try_from_parts(
+		initial_state: Q,
+		final_states: BTreeSet<Q>,
+		transitions: DetTransitions<Q>,
+	) -> Result<Self, DfaError<Q>>
+	where
+		Q: Clone,
+	{
+		for (q, q_transitions) in &transitions.0 {
+			let mut ranges: Vec<_> = q_transitions.keys().collect();
+			ranges.sort_by_key(|range| range.first());
 
-impl<Q, L> DetTransitions<Q, L> {
-	pub fn len(&self) -> usize {
-		self.0.values().fold(0, |x, map| x + map.len())
+			for pair in ranges.windows(2) {
+				if pair[0].last() >= pair[1].first() {
+					return Err(DfaError::Overlap(q.clone(), *pair[0], *pair[1]));
+				}
+			}
+		}
+
+		Ok(Self::from_parts(initial_state, final_states, transitions))
 	}
 
-	pub fn is_empty(&self) -> bool {
-		self.len() == 0
+	/// Returns a breadth-first iterator over the reachable states of the
+	/// automaton, each state being paired with a shortest string reaching it
+	/// from the initial state.
+	///
+	/// Each character of the path is the smallest character of the traversed
+	/// range. States are yielded in discovery order, transitions being
+	/// explored by increasing range, making the iteration deterministic.
+	pub fn bfs_with_paths(&self) -> BfsWithPaths<'_, Q> {
+		BfsWithPaths::new(self)
 	}
-}
 
-impl<Q, L> From<BTreeMap<Q, BTreeMap<L, Q>>> for DetTransitions<Q, L> {
-	fn from(value: BTreeMap<Q, BTreeMap<L, Q>>) -> Self {
-		Self(value)
+	/// Returns a shortest string recognized by the automaton, if any.
+	///
+	/// See [`Self::bfs_with_paths`] for how the characters are chosen.
+	pub fn shortest_word(&self) -> Option<String> {
+		self.bfs_with_paths()
+			.find(|(q, _)| self.is_final_state(q))
+			.map(|(_, path)| path)
 	}
-}
 
-pub struct DetSuccessors<'a, Q, L> {
-	inner: Option<std::collections::btree_map::Iter<'a, L, Q>>,
-}
+	/// Returns a shortest string recognized by both this automaton and
+	/// `other`, if any.
+	///
+	/// The string is a shortest word of the product of the two automata.
+	/// Returns `None` if their languages are disjoint. This is useful to
+	/// explain why two patterns (such as lexer rules) conflict.
+	pub fn intersection_witness<R: Ord>(&self, other: &DFA<R>) -> Option<String> {
+		let mut ids = BTreeMap::new();
+		self.product(
+			other,
+			|p, q| {
+				let len = ids.len();
+				*ids.entry((p, q)).or_insert(len)
+			},
+			range_intersection,
+		)
+		.shortest_word()
+	}
 
-impl<'a, Q, L> DetSuccessors<'a, Q, L> {
-	pub fn new(map: Option<&'a BTreeMap<L, Q>>) -> Self {
-		Self {
-			inner: map.map(|map| map.iter()),
+	/// Returns the state reached from `q` by reading the character `c`, if
+	/// any.
+	fn step(&self, q: &Q, c: char) -> Option<&Q> {
+		self.transitions.target(q, c)
+	}
+
+	/// Generates the source code of a Rust function recognizing the language of
+	/// this automaton.
+	///
+	/// The generated function has signature
+	/// `pub fn <fn_name>(input: &str) -> bool` and runs the automaton with a
+	/// `match` over the current state and character, without any dependency.
+	/// It is meant to be generated by a build script and embedded with
+	/// [`include!`]. The generated code compiles without warnings.
+	pub fn to_rust_source(&self, fn_name: &str) -> String {
+		use std::fmt::Write;
+
+		let states = self.all_states();
+
+		let index: BTreeMap<&Q, usize> = states.into_iter().zip(0..).collect();
+
+		let mut final_states = vec![false; index.len()];
+		for q in &self.final_states {
+			final_states[index[q]] = true
+		}
+
+		let mut result = String::new();
+		writeln!(result, "pub fn {fn_name}(input: &str) -> bool {{").unwrap();
+
+		// without transitions, a `match` would only have the `return false`
+		// arm, making the rest of the function unreachable.
+		if self.transitions.0.values().all(BTreeMap::is_empty) {
+			if self.is_final_state(&self.initial_state) {
+				writeln!(result, "\tinput.is_empty()").unwrap();
+			} else {
+				writeln!(result, "\tlet _ = input;").unwrap();
+				writeln!(result, "\tfalse").unwrap();
+			}
+
+			result.push_str("}\n");
+			return result;
+		}
+
+		writeln!(
+			result,
+			"\tconst FINAL_STATES: [bool; {}] = {final_states:?};",
+			final_states.len()
+		)
+		.unwrap();
+		writeln!(
+			result,
+			"\tlet mut state: usize = {};",
+			index[&self.initial_state]
+		)
+		.unwrap();
+		writeln!(result, "\tfor c in input.chars() {{").unwrap();
+		writeln!(result, "\t\tstate = match (state, c) {{").unwrap();
+
+		for (q, q_transitions) in &self.transitions.0 {
+			for (range, r) in q_transitions {
+				if let (Some(a), Some(b)) = (range.first(), range.last()) {
+					writeln!(
+						result,
+						"\t\t\t({}, {a:?}..={b:?}) => {},",
+						index[q], index[r]
+					)
+					.unwrap();
+				}
+			}
 		}
+
+		writeln!(result, "\t\t\t_ => return false,").unwrap();
+		writeln!(result, "\t\t}};").unwrap();
+		writeln!(result, "\t}}").unwrap();
+		writeln!(result, "\tFINAL_STATES[state]").unwrap();
+		result.push_str("}\n");
+
+		result
 	}
-}
 
-impl<'a, Q, L> Iterator for DetSuccessors<'a, Q, L> {
-	type Item = (&'a L, &'a Q);
+	/// Restricts the alphabet of the automaton to the given set of allowed
+	/// characters.
+	///
+	/// Every transition label is intersected with `allowed`, transitions with
+	/// an empty intersection being removed. Strings containing characters
+	/// outside of `allowed` are hence rejected. States may become dead ends in
+	/// the process.
+	pub fn restrict_alphabet(&self, allowed: &RangeSet<char>) -> DFA<Q>
+	where
+		Q: Clone,
+	{
+		let mut result = DFA::new(self.initial_state.clone());
+		result.final_states = self.final_states.clone();
 
-	fn next(&mut self) -> Option<Self::Item> {
-		self.inner.as_mut().and_then(|inner| inner.next())
+		for (q, q_transitions) in &self.transitions.0 {
+			result.declare_state(q.clone());
+
+			for (range, r) in q_transitions {
+				let mut label = RangeSet::new();
+				label.insert(*range);
+
+				for range in charset_intersection(&label, allowed).iter() {
+					result.add(q.clone(), *range, r.clone())
+				}
+			}
+		}
+
+		result
 	}
-}
 
-pub struct ReachableStates<'a, Q, L = AnyRange<char>> {
-	aut: &'a DFA<Q, L>,
-	visited: HashSet<&'a Q>,
-	stack: Vec<&'a Q>,
-}
+	/// Computes the complement of this automaton relative to `alphabet*`.
+	///
+	/// The resulting automaton recognizes every string made of characters
+	/// of `alphabet` that is not recognized by `self`. Strings containing
+	/// characters outside of `alphabet` are never recognized. States are
+	/// numbered in order, followed by a final sink state.
+	pub fn complement_over(&self, alphabet: &RangeSet<char>) -> DFA<u32> {
+		let states = self.all_states();
 
-impl<'a, Q, L> ReachableStates<'a, Q, L> {
-	fn new(aut: &'a DFA<Q, L>, q: &'a Q) -> Self {
-		Self {
-			aut,
-			visited: HashSet::new(),
-			stack: vec![q],
+		let index: BTreeMap<&Q, u32> = states.into_iter().zip(0..).collect();
+		let sink = index.len() as u32;
+
+		let mut result = DFA::new(index[&self.initial_state]);
+
+		for (&q, &i) in &index {
+			let mut uncovered = alphabet.clone();
+
+			for (range, r) in self.transitions_from(q) {
+				let mut label = RangeSet::new();
+				label.insert(*range);
+
+				for range in charset_intersection(&label, alphabet).iter() {
+					uncovered.remove(*range);
+					result.add(i, *range, index[r])
+				}
+			}
+
+			for range in uncovered.iter() {
+				result.add(i, *range, sink)
+			}
+
+			if !self.is_final_state(q) {
+				result.add_final_state(i);
+			}
+		}
+
+		for range in alphabet.iter() {
+			result.add(sink, *range, sink)
 		}
+
+		result.add_final_state(sink);
+		result
 	}
-}
 
-impl<'a, Q, L> Iterator for ReachableStates<'a, Q, L>
-where
-	Q: Ord + Eq + Hash,
-{
-	type Item = &'a Q;
+	/// Runs the automaton on `input` from its initial state, returning the
+	/// state reached after reading the whole input.
+	///
+	/// Returns `None` if the automaton cannot read some character of the
+	/// input. This is [`Automaton::run`] on the characters of `input`.
+	pub fn run(&self, input: &str) -> Option<&Q> {
+		Automaton::run(self, input.chars())
+	}
 
-	fn next(&mut self) -> Option<Self::Item> {
-		loop {
-			match self.stack.pop() {
-				Some(q) => {
-					if self.visited.insert(q) {
-						if let Some(q_transitions) = self.aut.transitions.0.get(q) {
-							for target in q_transitions.values() {
-								self.stack.push(target)
-							}
-						}
+	/// Runs the automaton on `input` from its initial state, until it reaches
+	/// the end of the input or a character it cannot read.
+	///
+	/// Returns the number of bytes read, along with the state reached if the
+	/// whole input was read. If the automaton dies, the offset is the one of
+	/// the first character it cannot read and no state is returned.
+	pub fn run_to_end(&self, input: &str) -> (usize, Option<&Q>) {
+		// offset of the last character given to the automaton.
+		let mut offset = 0;
+		let q = Automaton::run(
+			self,
+			input.char_indices().map(|(i, c)| {
+				offset = i;
+				c
+			}),
+		);
+
+		match q {
+			Some(q) => (input.len(), Some(q)),
+			None => (offset, None),
+		}
+	}
 
-						break Some(q);
-					}
+	/// Builds an automaton recognizing the reversed language of this one.
+	///
+	/// Every transition is flipped, the final states become the initial
+	/// states and the initial state becomes the only final state. The result
+	/// is generally nondeterministic, hence returned as an [`NFA`]. States are
+	/// numbered following the order of `Q`.
+	pub fn reverse(&self) -> NFA<u32> {
+		let index: BTreeMap<&Q, u32> = self.all_states().into_iter().zip(0..).collect();
+
+		let mut labels: BTreeMap<(u32, u32), RangeSet<char>> = BTreeMap::new();
+		for (q, q_transitions) in &self.transitions.0 {
+			for (range, r) in q_transitions {
+				labels
+					.entry((index[r], index[q]))
+					.or_default()
+					.insert(*range)
+			}
+		}
+
+		let mut result = NFA::new();
+		for &i in index.values() {
+			result.add_state(i)
+		}
+
+		for ((source, target), label) in labels {
+			result.add(source, Some(label), target)
+		}
+
+		for q in &self.final_states {
+			result.add_initial_state(index[q]);
+		}
+
+		result.add_final_state(index[&self.initial_state]);
+		result
+	}
+
+	/// Builds the quotient of this automaton by the given equivalence
+	/// relation on states.
+	///
+	/// Equivalent states are merged into a single state, with the union of
+	/// their transitions. A merged state is final if any of its states is
+	/// final. The relation is not required to preserve the language: merging
+	/// states generally makes the automaton recognize more strings. The
+	/// caller must ensure `same` is an equivalence relation.
+	///
+	/// Since merged transitions may overlap, the quotient is built as an
+	/// [`NFA`] then determinized again. The resulting states are hence
+	/// subsets of equivalence classes, numbered in order of discovery.
+	pub fn quotient(&self, same: impl Fn(&Q, &Q) -> bool) -> DFA<usize> {
+		let mut representatives: Vec<&Q> = Vec::new();
+		let mut class: BTreeMap<&Q, usize> = BTreeMap::new();
+		for q in self.all_states() {
+			let i = match representatives.iter().position(|r| same(r, q)) {
+				Some(i) => i,
+				None => {
+					representatives.push(q);
+					representatives.len() - 1
 				}
-				None => break None,
+			};
+
+			class.insert(q, i);
+		}
+
+		let mut nfa = NFA::new();
+		for &i in class.values() {
+			nfa.add_state(i)
+		}
+
+		for (q, q_transitions) in &self.transitions.0 {
+			for (range, r) in q_transitions {
+				let mut label = RangeSet::new();
+				label.insert(*range);
+				nfa.add(class[q], Some(label), class[r])
+			}
+		}
+
+		nfa.add_initial_state(class[&self.initial_state]);
+		for q in &self.final_states {
+			nfa.add_final_state(class[q]);
+		}
+
+		let mut ids = HashMap::new();
+		nfa.determinize(|det_q| {
+			let len = ids.len();
+			*ids.entry(det_q.clone()).or_insert(len)
+		})
+	}
+
+	/// Returns every state of the automaton, including states that are not
+	/// reachable from the initial state.
+	pub(crate) fn all_states(&self) -> BTreeSet<&Q> {
+		let mut states = BTreeSet::new();
+		states.insert(&self.initial_state);
+		states.extend(&self.final_states);
+		for (q, q_transitions) in &self.transitions.0 {
+			states.insert(q);
+			states.extend(q_transitions.values());
+		}
+
+		states
+	}
+
+	/// Partitions the states of the automaton into language-equivalence
+	/// classes, returning the class index of each state.
+	///
+	/// A missing transition is equivalent to a transition to an explicit dead
+	/// state.
+	fn equivalence_classes(&self) -> BTreeMap<&Q, usize> {
+		self.refine_classes(self.all_states(), None)
+	}
+
+	/// Splits the alphabet into ranges on which every state behaves the same,
+	/// and picks a representative character for each range.
+	fn alphabet_representatives(&self) -> Vec<char> {
+		let mut cuts = BTreeSet::new();
+		for range in self.transitions.0.values().flat_map(BTreeMap::keys) {
+			if let (Some(a), Some(b)) = (range.first(), range.last()) {
+				cuts.insert(a as u32);
+				cuts.insert(b as u32 + 1);
+			}
+		}
+
+		let cuts: Vec<_> = cuts.into_iter().collect();
+		cuts.iter()
+			.zip(cuts.iter().skip(1))
+			.filter_map(|(&a, &b)| {
+				let a = if (0xd800..0xe000).contains(&a) {
+					0xe000
+				} else {
+					a
+				};
+
+				if a < b {
+					char::from_u32(a)
+				} else {
+					None
+				}
+			})
+			.collect()
+	}
+
+	/// Checks if the states `p` and `q` recognize different languages,
+	/// meaning they cannot be merged by minimization.
+	///
+	/// Two states are distinguishable if some string is recognized from
+	/// exactly one of them. A missing transition hence behaves like a
+	/// transition to an explicit dead state. The states are compared using
+	/// the same partition as [`Self::redundant_state_pairs`] and
+	/// [`Self::is_minimal`]. A state unknown to the automaton is
+	/// distinguishable from every declared state.
+	pub fn are_distinguishable(&self, p: &Q, q: &Q) -> bool {
+		let classes = self.equivalence_classes();
+		classes.get(p) != classes.get(q)
+	}
+
+	/// Partitions the given states, closed under transitions, by Moore's
+	/// partition refinement, starting from the final/non-final partition.
+	///
+	/// The refinement runs on the completed automaton: missing transitions
+	/// lead to an implicit dead state, in the same class as the explicit
+	/// dead states.
+	///
+	/// At most `max_rounds` refinement rounds are performed, if given, the
+	/// result being coarser than the language-equivalence classes if the
+	/// refinement is stopped early.
+	fn refine_classes<'a>(
+		&'a self,
+		states: BTreeSet<&'a Q>,
+		max_rounds: Option<usize>,
+	) -> BTreeMap<&'a Q, usize> {
+		let representatives = self.alphabet_representatives();
+
+		// `None` is the implicit dead state.
+		let states: Vec<Option<&Q>> = std::iter::once(None)
+			.chain(states.into_iter().map(Some))
+			.collect();
+
+		let mut classes: BTreeMap<Option<&Q>, usize> = states
+			.iter()
+			.map(|q| (*q, q.map_or(false, |q| self.is_final_state(q)) as usize))
+			.collect();
+		let mut len = classes.values().collect::<BTreeSet<_>>().len();
+		let mut rounds = 0;
+		loop {
+			if max_rounds.map_or(false, |max| rounds >= max) {
+				break;
+			}
+
+			rounds += 1;
+			let mut ids = BTreeMap::new();
+			let mut next_classes = BTreeMap::new();
+			for q in &states {
+				let signature: Vec<_> = representatives
+					.iter()
+					.map(|c| classes[&q.and_then(|q| self.step(q, *c))])
+					.collect();
+				let next_len = ids.len();
+				let id = *ids.entry((classes[q], signature)).or_insert(next_len);
+				next_classes.insert(*q, id);
+			}
+
+			classes = next_classes;
+			if ids.len() == len {
+				break;
+			}
+
+			len = ids.len()
+		}
+
+		classes
+			.into_iter()
+			.filter_map(|(q, class)| Some((q?, class)))
+			.collect()
+	}
+
+	/// Estimates the number of states of the minimal automaton recognizing
+	/// the same language, without minimizing.
+	///
+	/// Only the reachable states are considered, partitioned into final and
+	/// non-final states, then refined once according to their transitions.
+	/// The result is a lower bound of the size of the minimal automaton, not
+	/// its exact size: more refinement rounds may be needed to distinguish
+	/// every state. If the estimate is close to the current number of
+	/// states, minimization is probably not worth it.
+	pub fn estimated_minimal_size(&self) -> usize {
+		let mut reachable = BTreeSet::new();
+		let mut stack = vec![&self.initial_state];
+		while let Some(q) = stack.pop() {
+			if reachable.insert(q) {
+				stack.extend(self.transitions_from(q).map(|(_, r)| r))
+			}
+		}
+
+		self.refine_classes(reachable, Some(1))
+			.into_values()
+			.collect::<BTreeSet<_>>()
+			.len()
+	}
+
+	/// Checks if this automaton is minimal: every state is reachable from the
+	/// initial state, and no two states recognize the same language.
+	///
+	/// A missing transition is equivalent to a transition to an explicit dead
+	/// state: a partial automaton with an explicit dead state is not minimal.
+	pub fn is_minimal(&self) -> bool {
+		let states = self.all_states();
+
+		let mut reachable = BTreeSet::new();
+		let mut stack = vec![&self.initial_state];
+		while let Some(q) = stack.pop() {
+			if reachable.insert(q) {
+				stack.extend(self.transitions_from(q).map(|(_, r)| r))
 			}
 		}
+
+		reachable.len() == states.len() && self.redundant_state_pairs().is_empty()
+	}
+
+	/// Returns every pair of distinct states recognizing the same residual
+	/// language, which would be merged by minimization.
+	///
+	/// A missing transition is equivalent to a transition to an explicit dead
+	/// state, so an explicit dead state and a state without transitions to
+	/// final states are redundant.
+	///
+	/// Each pair is listed once, with its smallest state first. The result is
+	/// empty if the automaton is minimal (up to unreachable states).
+	pub fn redundant_state_pairs(&self) -> Vec<(&Q, &Q)> {
+		let mut members: BTreeMap<usize, Vec<&Q>> = BTreeMap::new();
+		for (q, class) in self.equivalence_classes() {
+			members.entry(class).or_default().push(q)
+		}
+
+		let mut result = Vec::new();
+		for class in members.values() {
+			for (i, a) in class.iter().enumerate() {
+				for b in &class[i + 1..] {
+					result.push((*a, *b))
+				}
+			}
+		}
+
+		result
+	}
+
+	/// Compiles this automaton into a dense transition table, faster to run.
+	pub fn compile_dense(&self) -> DenseDfa {
+		DenseDfa::from_dfa(self)
+	}
+
+	/// Exports this automaton into a representation made only of primitive
+	/// values, suitable for FFI.
+	///
+	/// States are numbered following the order of `Q`.
+	pub fn to_flat(&self) -> FlatDfa {
+		FlatDfa::from_dfa(self)
+	}
+
+	/// Compiles this automaton into a flat transition table.
+	///
+	/// The initial state is numbered `0`, and the other states following the
+	/// order of `Q`. See [`NFA::compile_table`] to build the table without
+	/// building a [`DFA`] first.
+	pub fn to_table(&self) -> DfaTable {
+		DfaTable::from_dfa(self)
+	}
+
+	/// Returns the completion of this automaton, where every missing
+	/// transition leads to the dead state `None`.
+	fn completed(&self) -> DFA<Option<&Q>> {
+		let mut result = DFA::new(Some(&self.initial_state));
+
+		for q in self.all_states() {
+			let mut uncovered = any_char();
+
+			for (range, r) in self.transitions_from(q) {
+				uncovered.remove(*range);
+				result.add(Some(q), *range, Some(r))
+			}
+
+			for range in uncovered.iter() {
+				result.add(Some(q), *range, None)
+			}
+
+			if self.is_final_state(q) {
+				result.add_final_state(Some(q));
+			}
+		}
+
+		for range in any_char().iter() {
+			result.add(None, *range, None)
+		}
+
+		result
+	}
+
+	/// Builds an automaton recognizing the strings accepted by exactly one of
+	/// `self` and `other`.
+	///
+	/// Each state is a pair of states of `self` and `other`, `None` standing
+	/// for the (implicit) dead state. The result recognizes no string if and
+	/// only if both automata recognize the same language.
+	pub fn symmetric_difference<'a, 'b, R>(
+		&'a self,
+		other: &'b DFA<R>,
+	) -> DFA<(Option<&'a Q>, Option<&'b R>)>
+	where
+		Q: Hash,
+		R: Ord + Hash,
+	{
+		let a = self.completed();
+		let b = other.completed();
+		let mut result = a.product(&b, |p, q| (*p, *q), range_intersection);
+
+		result.final_states = result
+			.all_states()
+			.into_iter()
+			.filter(|(p, q)| {
+				p.map_or(false, |p| self.is_final_state(p))
+					!= q.map_or(false, |q| other.is_final_state(q))
+			})
+			.copied()
+			.collect();
+
+		result
+	}
+
+	/// Restricts the language of this automaton to the strings whose length
+	/// (in characters) is between `min` and `max`, inclusive.
+	///
+	/// The result is the product of `self` with a counting automaton with
+	/// `max + 1` states, hence has at most `(max + 1)` times as many states as
+	/// `self`. If `min > max`, the resulting language is empty.
+	pub fn intersect_length_range(&self, min: usize, max: usize) -> DFA<u32> {
+		let mut counter = DFA::new(0usize);
+		for n in 0..max {
+			for range in any_char().iter() {
+				counter.add(n, *range, n + 1)
+			}
+		}
+		for n in min..=max {
+			counter.add_final_state(n);
+		}
+
+		let mut ids = BTreeMap::new();
+		self.product(
+			&counter,
+			|q, n| {
+				let len = ids.len() as u32;
+				*ids.entry((q, *n)).or_insert(len)
+			},
+			range_intersection,
+		)
+	}
+
+	/// Checks if the automaton recognizes `input`.
+	///
+	/// Reading stops as soon as a character has no transition, without
+	/// reading the rest of the input.
+	pub fn accepts(&self, input: &str) -> bool {
+		self.accepts_chars(input.chars())
+	}
+
+	/// Checks if the automaton recognizes the given UTF-16 encoded input.
+	///
+	/// Surrogate pairs are decoded into a single character. An input
+	/// containing a lone surrogate is never recognized.
+	pub fn accepts_utf16(&self, input: &[u16]) -> bool {
+		let mut valid = true;
+		let chars = char::decode_utf16(input.iter().copied()).map_while(|c| {
+			valid &= c.is_ok();
+			c.ok()
+		});
+
+		self.accepts_chars(chars) && valid
+	}
+
+	/// Checks if the automaton recognizes the given characters, stopping at
+	/// the first character without transition.
+	///
+	/// This is the same as [`Self::accepts`], for a stream of characters that
+	/// is not stored as a string, such as the result of a normalization.
+	pub fn accepts_chars<I: IntoIterator<Item = char>>(&self, chars: I) -> bool {
+		Automaton::run(self, chars).map_or(false, |q| self.is_final_state(q))
+	}
+
+	/// Classifies `input` according to whether it is recognized, or could be
+	/// recognized by reading more characters.
+	///
+	/// The input is [`MatchStatus::Dead`] if it has no recognized extension,
+	/// either because the automaton cannot read some of its characters or
+	/// because no final state is reachable after reading it.
+	pub fn match_status(&self, input: &str) -> MatchStatus
+	where
+		Q: Hash,
+	{
+		match self.run(input) {
+			Some(q) if self.is_final_state(q) => MatchStatus::Complete,
+			Some(q)
+				if self
+					.reachable_states_from(q)
+					.any(|r| self.is_final_state(r)) =>
+			{
+				MatchStatus::Partial
+			}
+			_ => MatchStatus::Dead,
+		}
+	}
+
+	/// Runs the automaton on `input` and returns the payload associated to
+	/// the final state reached after reading the whole input.
+	///
+	/// This is useful when multiple patterns are compiled into a single
+	/// automaton, each final state being associated to the pattern it
+	/// recognizes. Returns `None` if the input is not recognized, or if the
+	/// reached final state has no payload.
+	pub fn run_classify<'p, T>(&self, input: &str, payloads: &'p BTreeMap<Q, T>) -> Option<&'p T> {
+		let q = self.run(input)?;
+
+		if self.is_final_state(q) {
+			payloads.get(q)
+		} else {
+			None
+		}
+	}
+
+	/// Runs the automaton on `input` from its initial state, returning every
+	/// (byte) position at which the automaton was in a final state, along with
+	/// this state.
+	///
+	/// The scan stops as soon as the automaton cannot read the next
+	/// character. This is the core primitive of lexers where distinct final
+	/// states denote distinct tokens: the caller can then select the longest
+	/// match, breaking ties by token priority.
+	pub fn scan(&self, input: &str) -> Vec<(usize, &Q)> {
+		let mut result = Vec::new();
+		let mut q = &self.initial_state;
+
+		if self.is_final_state(q) {
+			result.push((0, q))
+		}
+
+		for (i, c) in input.char_indices() {
+			match self.step(q, c) {
+				Some(r) => {
+					q = r;
+					if self.is_final_state(q) {
+						result.push((i + c.len_utf8(), q))
+					}
+				}
+				None => break,
+			}
+		}
+
+		result
+	}
+
+	/// Finds the leftmost-longest match of the automaton in `haystack`,
+	/// returning its (byte) range.
+	///
+	/// This is the same as [`DynMatcher::find`], which does not require
+	/// importing the trait.
+	pub fn find(&self, haystack: &str) -> Option<Range<usize>> {
+		DynMatcher::find(self, haystack)
+	}
+
+	/// Finds a match of the automaton in `haystack` according to the given
+	/// [`MatchMode`], returning its (byte) range.
+	///
+	/// This is the same as [`DynMatcher::find_with_mode`], which does not
+	/// require importing the trait.
+	pub fn find_with_mode(&self, haystack: &str, mode: MatchMode) -> Option<Range<usize>> {
+		DynMatcher::find_with_mode(self, haystack, mode)
+	}
+
+	/// Finds the longest suffix of `input` recognized by the automaton,
+	/// returning its start (byte) position.
+	///
+	/// This is the start of the match found with [`MatchMode::Suffix`] (as
+	/// with `pat$`), but the input is read only once. The
+	/// [reversed](Self::reverse) automaton is determinized then run over the
+	/// characters of `input` in reverse order, so this should not be called
+	/// repeatedly on the same automaton.
+	pub fn accepts_suffix(&self, input: &str) -> Option<usize> {
+		let (reversed, _) = self.reverse().determinize_with_mapping();
+		let mut q = reversed.initial_state();
+		let mut start = None;
+
+		if reversed.is_final_state(q) {
+			start = Some(input.len())
+		}
+
+		for (i, c) in input.char_indices().rev() {
+			match reversed.step(q, c) {
+				Some(r) => {
+					q = r;
+					if reversed.is_final_state(q) {
+						start = Some(i)
+					}
+				}
+				None => break,
+			}
+		}
+
+		start
+	}
+
+	/// Returns an iterator over every `(start, end)` (byte) position pair such
+	/// that the substring `haystack[start..end]` is recognized by the
+	/// automaton, including overlapping and nested matches.
+	///
+	/// The automaton is run from every position of the haystack, so the
+	/// complexity is quadratic in the length of the haystack. The number of
+	/// reported matches can be bounded using
+	/// [`OverlappingMatches::with_max_matches`].
+	pub fn find_overlapping_iter<'a>(&'a self, haystack: &'a str) -> OverlappingMatches<'a, Q> {
+		OverlappingMatches::new(self, haystack)
+	}
+
+	/// Returns an iterator over every (byte) position of `haystack` at which
+	/// the automaton is in a final state, running it once from the initial
+	/// state.
+	///
+	/// When the automaton recognizes `.*pat`, such as the determinization of a
+	/// pattern prefixed with [`Quantifier::Star`](crate::nfa::Quantifier::Star)
+	/// over [`any_char`], this is the end position of every match of `pat`,
+	/// including overlapping ones. Unlike [`Self::find_overlapping_iter`],
+	/// the haystack is read only once, but the start of the matches is not
+	/// reported. The iteration stops as soon as the automaton cannot read the
+	/// next character.
+	pub fn find_overlapping<'a>(&'a self, haystack: &'a str) -> impl 'a + Iterator<Item = usize> {
+		let mut q = &self.initial_state;
+		let empty_match = self.is_final_state(q).then_some(0);
+
+		empty_match.into_iter().chain(
+			haystack
+				.char_indices()
+				.map_while(move |(i, c)| {
+					q = self.step(q, c)?;
+					Some((i + c.len_utf8(), q))
+				})
+				.filter(|(_, q)| self.is_final_state(q))
+				.map(|(end, _)| end),
+		)
+	}
+
+	/// Splits `input` into tokens, using the payloads of the final states as
+	/// token kinds.
+	///
+	/// Tokens are recognized with the maximal munch rule: from the current
+	/// position, the longest non-empty prefix reaching a final state with a
+	/// payload is selected, and the lexer resumes right after it. Each item
+	/// is the `(start, end)` (byte) range of a token along with its payload.
+	/// If no token can be recognized at some position, a [`LexError`] is
+	/// returned and the iteration stops. Tokens to be skipped, such as
+	/// whitespaces, must be filtered out by the caller.
+	pub fn lex<'a, T>(&'a self, input: &'a str, payloads: &'a BTreeMap<Q, T>) -> Lexer<'a, Q, T> {
+		Lexer {
+			aut: self,
+			input,
+			payloads,
+			offset: Some(0),
+		}
+	}
+
+	/// Computes the set of characters appearing in every string recognized by
+	/// the automaton.
+	///
+	/// A character `c` is in the returned set if and only if the automaton
+	/// recognizes at least one string, and every recognized string contains
+	/// `c`. Such characters can be used to quickly discard a haystack before
+	/// running the automaton.
+	pub fn required_chars(&self) -> RangeSet<char> {
+		let mut result = RangeSet::new();
+
+		if self.accepts_avoiding(None) {
+			// A character can only be avoided by a transition if it is its only
+			// label.
+			let candidates: BTreeSet<char> = self
+				.transitions
+				.0
+				.values()
+				.flat_map(BTreeMap::keys)
+				.filter_map(single_char)
+				.collect();
+
+			for c in candidates {
+				if !self.accepts_avoiding(Some(c)) {
+					result.insert(c);
+				}
+			}
+		}
+
+		result
+	}
+
+	/// Checks if a final state can be reached without reading the character
+	/// `c`.
+	fn accepts_avoiding(&self, c: Option<char>) -> bool {
+		let mut visited = BTreeSet::new();
+		let mut stack = vec![&self.initial_state];
+
+		while let Some(q) = stack.pop() {
+			if visited.insert(q) {
+				if self.is_final_state(q) {
+					return true;
+				}
+
+				for (range, r) in self.successors(q) {
+					if c.is_none() || single_char(range) != c {
+						stack.push(r)
+					}
+				}
+			}
+		}
+
+		false
+	}
+}
+
+impl DFA<u32> {
+	/// Imports an automaton exported with [`Self::to_flat`].
+	///
+	/// Fails if a state is out of bounds, if a range bound is not a
+	/// character, or if the transitions are not deterministic.
+	pub fn from_flat(flat: &FlatDfa) -> Result<Self, FlatDfaError> {
+		flat.to_dfa()
+	}
+}
+
+impl<Q: Ord> Automaton<char> for DFA<Q> {
+	type State<'a>
+		= &'a Q
+	where
+		Self: 'a;
+
+	fn initial_state(&self) -> Option<Self::State<'_>> {
+		Some(&self.initial_state)
+	}
+
+	fn next_state<'a>(&'a self, q: Self::State<'a>, token: char) -> Option<Self::State<'a>> {
+		self.step(q, token)
+	}
+
+	fn is_final_state<'a>(&'a self, q: &Self::State<'a>) -> bool {
+		self.final_states.contains(*q)
+	}
+}
+
+/// Invalid DFA error, returned by [`DFA::try_from_parts`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DfaError<Q> {
+	/// Two transitions leaving the same state have overlapping labels.
+	#[error("overlapping transitions `{1:?}` and `{2:?}` from state `{0:?}`")]
+	Overlap(Q, AnyRange<char>, AnyRange<char>),
+}
+
+/// Status of an input, returned by [`DFA::match_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchStatus {
+	/// The input is not recognized, and no extension of it is.
+	Dead,
+
+	/// The input is not recognized, but some extension of it is.
+	Partial,
+
+	/// The input is recognized.
+	Complete,
+}
+
+/// Computes the intersection of two ranges, if not empty.
+fn range_intersection(a: &AnyRange<char>, b: &AnyRange<char>) -> Option<AnyRange<char>> {
+	let first = a.first()?.max(b.first()?);
+	let last = a.last()?.min(b.last()?);
+
+	if first <= last {
+		Some((first..=last).into())
+	} else {
+		None
+	}
+}
+
+/// Checks if the given range contains the character `c`.
+fn range_contains(range: &AnyRange<char>, c: char) -> bool {
+	range.intersects(&AnyRange::from(c..=c))
+}
+
+/// Returns the only character of the given range, if any.
+fn single_char(range: &AnyRange<char>) -> Option<char> {
+	match (range.first(), range.last()) {
+		(Some(a), Some(b)) if a == b => Some(a),
+		_ => None,
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DetTransitions<Q, L = AnyRange<char>>(BTreeMap<Q, BTreeMap<L, Q>>);
+
+impl<Q, L> Default for DetTransitions<Q, L> {
+	fn default() -> Self {
+		Self(BTreeMap::new())
+	}
+}
+
+impl<Q, L> DetTransitions<Q, L> {
+	/// Creates an empty set of transitions.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn len(&self) -> usize {
+		self.0.values().fold(0, |x, map| x + map.len())
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl<Q: Ord, L: Ord> DetTransitions<Q, L> {
+	/// Adds a transition, returning the previous target of the same label
+	/// from `source`, if any.
+	///
+	/// The labels of the transitions leaving the same state must be disjoint
+	/// for the automaton to be deterministic.
+	pub fn insert(&mut self, source: Q, label: L, target: Q) -> Option<Q> {
+		self.0.entry(source).or_default().insert(label, target)
+	}
+
+	/// Returns an iterator over the `(label, target)` transitions leaving
+	/// `q`.
+	pub fn successors(&self, q: &Q) -> DetSuccessors<'_, Q, L> {
+		DetSuccessors::new(self.0.get(q))
+	}
+}
+
+impl<Q: Ord> DetTransitions<Q> {
+	/// Returns the state reached from `q` by reading the character `c`, if
+	/// any.
+	///
+	/// ```
+	/// use iregex_automata::dfa::DetTransitions;
+	///
+	/// let transitions: DetTransitions<u32> = DetTransitions::from_iter([
+	///     (0, ('a'..='z').into(), 1),
+	///     (1, ('0'..='9').into(), 0),
+	/// ]);
+	///
+	/// assert_eq!(transitions.target(&0, 'q'), Some(&1));
+	/// assert_eq!(transitions.target(&0, '5'), None);
+	/// ```
+	pub fn target(&self, q: &Q, c: char) -> Option<&Q> {
+		// labels are disjoint, so only the last one starting before `c` may
+		// contain it.
+		let bound = AnyRange::new(Bound::Included(c), Bound::Unbounded);
+		self.0
+			.get(q)?
+			.range(..=bound)
+			.next_back()
+			.filter(|(range, _)| range_contains(range, c))
+			.map(|(_, r)| r)
+	}
+}
+
+/// Builds transitions from `(source, label, target)` triples.
+///
+/// ```
+/// use std::collections::BTreeSet;
+/// use iregex_automata::{dfa::DetTransitions, DFA};
+///
+/// // `[a-z]+`
+/// let transitions: DetTransitions<u32> = DetTransitions::from_iter([
+///     (0, ('a'..='z').into(), 1),
+///     (1, ('a'..='z').into(), 1),
+/// ]);
+/// let dfa = DFA::from_parts(0, BTreeSet::from([1]), transitions);
+///
+/// assert!(dfa.accepts("abc"));
+/// assert!(!dfa.accepts("ab1"));
+/// ```
+impl<Q: Ord, L: Ord> FromIterator<(Q, L, Q)> for DetTransitions<Q, L> {
+	fn from_iter<T: IntoIterator<Item = (Q, L, Q)>>(iter: T) -> Self {
+		let mut result = Self::new();
+
+		for (source, label, target) in iter {
+			result.insert(source, label, target);
+		}
+
+		result
+	}
+}
+
+impl<Q, L> From<BTreeMap<Q, BTreeMap<L, Q>>> for DetTransitions<Q, L> {
+	fn from(value: BTreeMap<Q, BTreeMap<L, Q>>) -> Self {
+		Self(value)
+	}
+}
+
+impl<Q, L> From<DetTransitions<Q, L>> for BTreeMap<Q, BTreeMap<L, Q>> {
+	fn from(value: DetTransitions<Q, L>) -> Self {
+		value.0
+	}
+}
+
+pub struct DetSuccessors<'a, Q, L> {
+	inner: Option<std::collections::btree_map::Iter<'a, L, Q>>,
+}
+
+impl<'a, Q, L> DetSuccessors<'a, Q, L> {
+	pub fn new(map: Option<&'a BTreeMap<L, Q>>) -> Self {
+		Self {
+			inner: map.map(|map| map.iter()),
+		}
+	}
+}
+
+impl<'a, Q, L> Iterator for DetSuccessors<'a, Q, L> {
+	type Item = (&'a L, &'a Q);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.as_mut().and_then(|inner| inner.next())
+	}
+}
+
+pub struct ReachableStates<'a, Q, L = AnyRange<char>> {
+	aut: &'a DFA<Q, L>,
+	visited: HashSet<&'a Q>,
+	stack: Vec<&'a Q>,
+}
+
+impl<'a, Q, L> ReachableStates<'a, Q, L> {
+	fn new(aut: &'a DFA<Q, L>, q: &'a Q) -> Self {
+		Self {
+			aut,
+			visited: HashSet::new(),
+			stack: vec![q],
+		}
+	}
+}
+
+impl<'a, Q, L> Iterator for ReachableStates<'a, Q, L>
+where
+	Q: Ord + Eq + Hash,
+{
+	type Item = &'a Q;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			match self.stack.pop() {
+				Some(q) => {
+					if self.visited.insert(q) {
+						if let Some(q_transitions) = self.aut.transitions.0.get(q) {
+							for target in q_transitions.values() {
+								self.stack.push(target)
+							}
+						}
+
+						break Some(q);
+					}
+				}
+				None => break None,
+			}
+		}
+	}
+}
+
+/// Breadth-first iterator over the states of a [`DFA`], with the shortest
+/// path reaching each state.
+///
+/// See [`DFA::bfs_with_paths`].
+pub struct BfsWithPaths<'a, Q> {
+	aut: &'a DFA<Q>,
+	visited: BTreeSet<&'a Q>,
+	queue: VecDeque<(&'a Q, String)>,
+}
+
+impl<'a, Q: Ord> BfsWithPaths<'a, Q> {
+	fn new(aut: &'a DFA<Q>) -> Self {
+		let mut visited = BTreeSet::new();
+		visited.insert(&aut.initial_state);
+
+		Self {
+			aut,
+			visited,
+			queue: VecDeque::from([(&aut.initial_state, String::new())]),
+		}
+	}
+}
+
+impl<'a, Q: Ord> Iterator for BfsWithPaths<'a, Q> {
+	type Item = (&'a Q, String);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let (q, path) = self.queue.pop_front()?;
+
+		for (range, r) in self.aut.successors(q) {
+			if let Some(c) = range.first() {
+				if self.visited.insert(r) {
+					let mut r_path = path.clone();
+					r_path.push(c);
+					self.queue.push_back((r, r_path))
+				}
+			}
+		}
+
+		Some((q, path))
+	}
+}
+
+/// Error returned by [`Lexer`] when no token can be recognized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("no token recognized at offset {offset}")]
+pub struct LexError {
+	/// (Byte) offset at which no token can be recognized.
+	pub offset: usize,
+}
+
+/// Iterator over the tokens of an input.
+///
+/// See [`DFA::lex`].
+pub struct Lexer<'a, Q, T> {
+	aut: &'a DFA<Q>,
+	input: &'a str,
+	payloads: &'a BTreeMap<Q, T>,
+
+	/// Current offset, or `None` once an error has been returned.
+	offset: Option<usize>,
+}
+
+impl<'a, Q: Ord, T> Iterator for Lexer<'a, Q, T> {
+	type Item = Result<(usize, usize, &'a T), LexError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let start = self.offset?;
+		if start == self.input.len() {
+			return None;
+		}
+
+		let token = self
+			.aut
+			.scan(&self.input[start..])
+			.into_iter()
+			.rev()
+			.find_map(|(len, q)| match self.payloads.get(q) {
+				Some(payload) if len > 0 => Some((start + len, payload)),
+				_ => None,
+			});
+
+		match token {
+			Some((end, payload)) => {
+				self.offset = Some(end);
+				Some(Ok((start, end, payload)))
+			}
+			None => {
+				self.offset = None;
+				Some(Err(LexError { offset: start }))
+			}
+		}
+	}
+}
+
+/// Iterator over the overlapping matches of a [`DFA`] in a haystack.
+///
+/// See [`DFA::find_overlapping_iter`].
+pub struct OverlappingMatches<'a, Q> {
+	aut: &'a DFA<Q>,
+	haystack: &'a str,
+	start: usize,
+	next_start: Option<usize>,
+	current: Option<(&'a Q, usize)>,
+	max_matches: Option<usize>,
+}
+
+impl<'a, Q> OverlappingMatches<'a, Q> {
+	fn new(aut: &'a DFA<Q>, haystack: &'a str) -> Self {
+		Self {
+			aut,
+			haystack,
+			start: 0,
+			next_start: Some(0),
+			current: None,
+			max_matches: None,
+		}
+	}
+
+	/// Stops the iteration after the given number of matches.
+	pub fn with_max_matches(mut self, max_matches: usize) -> Self {
+		self.max_matches = Some(max_matches);
+		self
+	}
+}
+
+impl<'a, Q: Ord> Iterator for OverlappingMatches<'a, Q> {
+	type Item = (usize, usize);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if self.max_matches == Some(0) {
+				break None;
+			}
+
+			match self.current.take() {
+				Some((q, end)) => {
+					if let Some(c) = self.haystack[end..].chars().next() {
+						self.current = self.aut.step(q, c).map(|r| (r, end + c.len_utf8()))
+					}
+
+					if self.aut.is_final_state(q) {
+						if let Some(max_matches) = &mut self.max_matches {
+							*max_matches -= 1
+						}
+
+						break Some((self.start, end));
+					}
+				}
+				None => {
+					self.start = self.next_start?;
+					self.next_start = self.haystack[self.start..]
+						.chars()
+						.next()
+						.map(|c| self.start + c.len_utf8());
+					self.current = Some((&self.aut.initial_state, self.start))
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		any_char,
+		nfa::{Quantifier, StateBuilder, U32StateBuilder},
+		NFA,
+	};
+
+	fn any_star(state_builder: &mut U32StateBuilder) -> NFA {
+		let mut nfa = NFA::new();
+		let q = state_builder.next_state(&mut nfa).unwrap();
+		nfa.add(q, Some(any_char()), q);
+		nfa.add_initial_state(q);
+		nfa.add_final_state(q);
+		nfa
+	}
+
+	fn determinize(nfa: &NFA) -> DFA<usize> {
+		let mut ids = HashMap::new();
+		nfa.determinize(|q| {
+			let len = ids.len();
+			*ids.entry(q.clone()).or_insert(len)
+		})
+	}
+
+	#[test]
+	fn remove_sink() {
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('a'..='a').into(), 1);
+		dfa.add_final_state(1);
+
+		let mut complete = dfa.clone();
+		complete.add(0, ('\u{0}'..='`').into(), 2);
+		complete.add(0, ('b'..='\u{d7ff}').into(), 2);
+		complete.add(0, ('\u{e000}'..='\u{10ffff}').into(), 2);
+		for range in any_char().iter() {
+			complete.add(1, *range, 2);
+			complete.add(2, *range, 2);
+		}
+
+		assert_eq!(complete.remove_sink(), dfa)
+	}
+
+	/// Returns the number of states of the minimized automaton, checking that
+	/// no final state is lost.
+	fn minimal_len(dfa: &DFA<usize>) -> usize {
+		let minimal = dfa.minimize(dfa.partition(|q| dfa.is_final_state(q)).into_values());
+		let states = minimal.states();
+		assert_eq!(
+			states.iter().any(|q| minimal.is_final_state(q)),
+			dfa.states().iter().any(|q| dfa.is_final_state(q))
+		);
+		states.len()
+	}
+
+	#[test]
+	fn minimize_degenerate() {
+		let mut state_builder = U32StateBuilder::default();
+		let dead = usize::MAX;
+
+		// the empty language is a single non-final state.
+		let mut empty = NFA::new();
+		let q = state_builder.next_state(&mut empty).unwrap();
+		empty.add_initial_state(q);
+		assert_eq!(minimal_len(&determinize(&empty)), 1);
+		let mut ids = HashMap::new();
+		let complete = empty.determinize_complete(
+			|q| {
+				let len = ids.len();
+				*ids.entry(q.clone()).or_insert(len)
+			},
+			dead,
+		);
+		assert_eq!(minimal_len(&complete), 1);
+
+		// the universal language is a single final looping state.
+		let universal = any_star(&mut state_builder);
+		assert_eq!(minimal_len(&determinize(&universal)), 1);
+
+		// the empty string only, with a dead state to reject anything else.
+		let mut epsilon = NFA::new();
+		let q = state_builder.next_state(&mut epsilon).unwrap();
+		let r = state_builder.next_state(&mut epsilon).unwrap();
+		epsilon.add(q, None, r);
+		epsilon.add_initial_state(q);
+		epsilon.add_final_state(r);
+		let mut ids = HashMap::new();
+		let complete = epsilon.determinize_complete(
+			|q| {
+				let len = ids.len();
+				*ids.entry(q.clone()).or_insert(len)
+			},
+			dead,
+		);
+		assert_eq!(minimal_len(&complete), 2);
+
+		// a single literal.
+		let literal = NFA::from_chars("abcd".chars(), &mut state_builder).unwrap();
+		assert_eq!(minimal_len(&determinize(&literal)), 5);
+	}
+
+	#[test]
+	fn accepts_empty() {
+		let a = || Some(RangeSet::from_iter(['a']));
+
+		// initial state is final.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, a(), 1);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(0);
+		assert!(nfa.recognizes_empty());
+		assert!(determinize(&nfa).accepts_empty());
+
+		// chain of epsilon-transitions to a final state.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add(1, None, 2);
+		nfa.add(2, a(), 3);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(2);
+		assert!(nfa.recognizes_empty());
+		assert!(determinize(&nfa).accepts_empty());
+
+		// no epsilon path to a final state.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add(1, a(), 2);
+		nfa.add(2, None, 3);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(3);
+		assert!(!nfa.recognizes_empty());
+		assert!(!determinize(&nfa).accepts_empty());
+	}
+
+	#[test]
+	fn to_rust_source() {
+		let nfa = NFA::from_class_sequence(
+			&[
+				(['a'..='c'].into_iter().collect(), Quantifier::Plus),
+				(['\''].into_iter().collect(), Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa);
+
+		let source = dfa.to_rust_source("is_abc_quote");
+		assert!(source.starts_with("pub fn is_abc_quote(input: &str) -> bool {\n"));
+		assert!(source.contains("const FINAL_STATES: [bool; 3] = [false, false, true];"));
+		assert!(source.contains("let mut state: usize = 0;"));
+		assert!(source.contains("(0, 'a'..='c') => 1,"));
+		assert!(source.contains("(1, 'a'..='c') => 1,"));
+		assert!(source.contains("(1, '\\''..='\\'') => 2,"));
+	}
+
+	#[test]
+	fn transitions_target() {
+		let transitions: DetTransitions<u32> = DetTransitions::from_iter([
+			(0, ('a'..='a').into(), 1),
+			(0, ('c'..='f').into(), 2),
+			(0, ('x'..).into(), 3),
+			(1, ('\0'..='z').into(), 0),
+		]);
+
+		assert_eq!(transitions.target(&0, '`'), None);
+		assert_eq!(transitions.target(&0, 'a'), Some(&1));
+		assert_eq!(transitions.target(&0, 'b'), None);
+		assert_eq!(transitions.target(&0, 'c'), Some(&2));
+		assert_eq!(transitions.target(&0, 'f'), Some(&2));
+		assert_eq!(transitions.target(&0, 'g'), None);
+		assert_eq!(transitions.target(&0, 'x'), Some(&3));
+		assert_eq!(transitions.target(&0, char::MAX), Some(&3));
+		assert_eq!(transitions.target(&1, '\0'), Some(&0));
+		assert_eq!(transitions.target(&1, '{'), None);
+		assert_eq!(transitions.target(&2, 'a'), None);
+	}
+
+	#[test]
+	fn accepts() {
+		let nfa = NFA::from_class_sequence(
+			&[(['a'].into_iter().collect(), Quantifier::Plus)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa);
+
+		assert!(dfa.accepts("aaa"));
+		assert!(!dfa.accepts(""));
+		assert!(!dfa.accepts("aab"));
+
+		let input = "ab".to_string() + &"a".repeat(10_000);
+		let mut consumed = 0;
+		assert!(!dfa.accepts_chars(input.chars().inspect(|_| consumed += 1)));
+		assert_eq!(consumed, 2);
+	}
+
+	#[test]
+	fn match_status() {
+		let nfa = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		let mut dfa = determinize(&nfa);
+
+		// dead-end state, from which no final state is reachable.
+		dfa.add(dfa.initial_state, ('x'..='x').into(), usize::MAX);
+
+		assert_eq!(dfa.match_status("abc"), MatchStatus::Complete);
+		assert_eq!(dfa.match_status(""), MatchStatus::Partial);
+		assert_eq!(dfa.match_status("ab"), MatchStatus::Partial);
+		assert_eq!(dfa.match_status("abd"), MatchStatus::Dead);
+		assert_eq!(dfa.match_status("abcd"), MatchStatus::Dead);
+		assert_eq!(dfa.match_status("x"), MatchStatus::Dead);
+	}
+
+	#[test]
+	fn is_minimal() {
+		// minimal automaton for `a[a-b]*`.
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('a'..='a').into(), 1);
+		dfa.add(1, ('a'..='b').into(), 1);
+		dfa.add_final_state(1);
+		assert!(dfa.is_minimal());
+
+		// the same language, with a redundant state.
+		let mut redundant: DFA<u32> = DFA::new(0);
+		redundant.add(0, ('a'..='a').into(), 1);
+		redundant.add(1, ('b'..='b').into(), 2);
+		redundant.add(1, ('a'..='a').into(), 1);
+		redundant.add(2, ('a'..='a').into(), 1);
+		redundant.add(2, ('b'..='b').into(), 2);
+		redundant.add_final_state(1);
+		redundant.add_final_state(2);
+		assert!(!redundant.is_minimal());
+
+		// unreachable state.
+		let mut unreachable = dfa.clone();
+		unreachable.add(3, ('c'..='c').into(), 1);
+		assert!(!unreachable.is_minimal());
+
+		let nfa = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		assert!(determinize(&nfa).is_minimal());
+
+		// an explicit dead state is redundant with a state whose transitions
+		// are all missing.
+		let mut dead = dfa.clone();
+		dead.add(0, ('b'..='b').into(), 2);
+		assert!(dead.is_minimal());
+		dead.add(1, ('c'..='c').into(), 3);
+		dead.add(2, ('a'..='c').into(), 2);
+		assert!(!dead.is_minimal());
+
+		// a single dead state is not redundant.
+		let mut complete: DFA<u32> = DFA::new(0);
+		complete.add(0, ('a'..='a').into(), 1);
+		complete.add(0, ('b'..='b').into(), 2);
+		complete.add(1, ('a'..='b').into(), 1);
+		complete.add(2, ('a'..='b').into(), 2);
+		complete.add_final_state(1);
+		assert!(complete.is_minimal());
+	}
+
+	#[test]
+	fn complement_over() {
+		let nfa = NFA::from_chars("a".chars(), U32StateBuilder::default()).unwrap();
+		let dfa = determinize(&nfa);
+
+		let alphabet = ['a'..='c'].into_iter().collect();
+		let complement = dfa.complement_over(&alphabet);
+
+		for input in ["", "b", "c", "aa", "ab", "cab"] {
+			assert!(complement.accepts(input), "{input:?}")
+		}
+
+		for input in ["a", "d", "ad", "é"] {
+			assert!(!complement.accepts(input), "{input:?}")
+		}
+	}
+
+	#[test]
+	fn redundant_state_pairs() {
+		// `a[ab]*`, with the states 1 and 2 being equivalent.
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('a'..='a').into(), 1);
+		dfa.add(1, ('a'..='a').into(), 1);
+		dfa.add(1, ('b'..='b').into(), 2);
+		dfa.add(2, ('a'..='a').into(), 1);
+		dfa.add(2, ('b'..='b').into(), 2);
+		dfa.add_final_state(1);
+		dfa.add_final_state(2);
+		assert_eq!(dfa.redundant_state_pairs(), [(&1, &2)]);
+
+		let nfa = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		assert!(determinize(&nfa).redundant_state_pairs().is_empty());
+
+		// `1` leads to `3`, `2` has no transition, and `3` is an explicit dead
+		// state: all recognize the empty language.
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('x'..='x').into(), 1);
+		dfa.add(0, ('y'..='y').into(), 2);
+		dfa.add(0, ('z'..='z').into(), 4);
+		dfa.add(1, ('a'..='a').into(), 3);
+		dfa.add_final_state(4);
+		assert_eq!(dfa.redundant_state_pairs(), [(&1, &2), (&1, &3), (&2, &3)]);
+	}
+
+	#[test]
+	fn symmetric_difference() {
+		let mut state_builder = U32StateBuilder::default();
+		let abc = determinize(&NFA::from_chars("abc".chars(), &mut state_builder).unwrap());
+		let abd = determinize(&NFA::from_chars("abd".chars(), &mut state_builder).unwrap());
+
+		let same = abc.symmetric_difference(&abc);
+		assert!(same.final_states().is_empty());
+
+		let diff = abc.symmetric_difference(&abd);
+		assert!(diff.accepts("abc"));
+		assert!(diff.accepts("abd"));
+		for input in ["", "ab", "abe", "abcd"] {
+			assert!(!diff.accepts(input), "{input:?}")
+		}
+
+		let any = determinize(&any_star(&mut state_builder));
+		let diff = abc.symmetric_difference(&any);
+		assert!(diff.accepts(""));
+		assert!(diff.accepts("abd"));
+		assert!(!diff.accepts("abc"));
+	}
+
+	#[test]
+	fn try_from_parts() {
+		let disjoint = DetTransitions::from_iter([
+			(0, ('a'..='c').into(), 1),
+			(0, ('d'..='d').into(), 2),
+			(1, ('a'..='z').into(), 2),
+		]);
+		assert!(DFA::try_from_parts(0, BTreeSet::from([2]), disjoint).is_ok());
+
+		let overlapping =
+			DetTransitions::from_iter([(0, ('a'..='c').into(), 1), (0, ('c'..='d').into(), 2)]);
+		assert_eq!(
+			DFA::try_from_parts(0, BTreeSet::from([2]), overlapping),
+			Err(DfaError::Overlap(0, ('a'..='c').into(), ('c'..='d').into()))
+		);
+	}
+
+	#[test]
+	fn shortest_word() {
+		let nfa = NFA::from_class_sequence(
+			&[
+				(['x'..='z'].into_iter().collect(), Quantifier::Star),
+				(['b'..='c'].into_iter().collect(), Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		assert_eq!(determinize(&nfa).shortest_word().as_deref(), Some("b"));
+
+		let empty: DFA<u32> = DFA::new(0);
+		assert_eq!(empty.shortest_word(), None);
+	}
+
+	#[test]
+	fn intersect_length_range() {
+		let nfa = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['a'..='z']), Quantifier::Star)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa).intersect_length_range(2, 3);
+
+		assert!(dfa.accepts("ab"));
+		assert!(dfa.accepts("abc"));
+		assert!(!dfa.accepts(""));
+		assert!(!dfa.accepts("a"));
+		assert!(!dfa.accepts("abcd"));
+		assert!(!dfa.accepts("a1"));
+
+		assert!(!determinize(&nfa).intersect_length_range(3, 2).accepts("ab"));
+	}
+
+	#[test]
+	fn accepts_chars() {
+		let dfa = determinize(&NFA::from_chars("cba".chars(), U32StateBuilder::default()).unwrap());
+
+		assert!(dfa.accepts_chars("abc".chars().rev()));
+		assert!(!dfa.accepts_chars("abc".chars()));
+		assert!(!dfa.accepts_chars("abcd".chars().rev()));
+		assert!(dfa.accepts_chars("c-b-a".chars().filter(|c| *c != '-')));
+	}
+
+	#[test]
+	fn quotient() {
+		// `ab`
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('a'..='a').into(), 1);
+		dfa.add(1, ('b'..='b').into(), 2);
+		dfa.add_final_state(2);
+
+		let identity = dfa.quotient(|p, q| p == q);
+		assert_eq!(identity.symmetric_difference(&dfa).shortest_word(), None);
+
+		// merging `0` and `1` gives `a*b`.
+		let merged = dfa.quotient(|p, q| p == q || (*p < 2 && *q < 2));
+		assert_eq!(merged.all_states().len(), 2);
+		for input in ["b", "ab", "aaab"] {
+			assert!(merged.accepts(input), "{input:?}")
+		}
+		for input in ["", "a", "ba", "abb"] {
+			assert!(!merged.accepts(input), "{input:?}")
+		}
+	}
+
+	#[test]
+	fn run_to_end() {
+		let dfa = determinize(&NFA::from_chars("aéb".chars(), U32StateBuilder::default()).unwrap());
+
+		assert_eq!(dfa.run_to_end("aéc"), (3, None));
+		assert_eq!(dfa.run_to_end("x"), (0, None));
+
+		let (len, q) = dfa.run_to_end("aé");
+		assert_eq!(len, 3);
+		assert!(!dfa.is_final_state(q.unwrap()));
+
+		let (len, q) = dfa.run_to_end("aéb");
+		assert_eq!(len, 4);
+		assert!(dfa.is_final_state(q.unwrap()));
+	}
+
+	#[test]
+	fn intersection_witness() {
+		let mut state_builder = U32StateBuilder::default();
+		let mut words = |words: &[&str]| {
+			let mut nfa = NFA::new();
+			for w in words {
+				nfa.union(NFA::from_chars(w.chars(), &mut state_builder).unwrap())
+			}
+			determinize(&nfa)
+		};
+
+		let a = words(&["for", "foo", "if"]);
+		let b = words(&["bar", "food", "foo", "if"]);
+		let c = words(&["while", "fo"]);
+
+		assert_eq!(a.intersection_witness(&b).as_deref(), Some("if"));
+		assert_eq!(b.intersection_witness(&a).as_deref(), Some("if"));
+		assert_eq!(a.intersection_witness(&c), None);
+
+		let any = determinize(&any_star(&mut U32StateBuilder::default()));
+		assert_eq!(a.intersection_witness(&any).as_deref(), Some("if"));
+	}
+
+	#[test]
+	fn estimated_minimal_size() {
+		let minimal =
+			determinize(&NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap());
+		assert!(minimal.is_minimal());
+		// `0` and `1` only lead to non-final states after one character.
+		assert_eq!(minimal.estimated_minimal_size(), 3);
+
+		// `aaa`, with distinct states only told apart after more rounds.
+		let chain =
+			determinize(&NFA::from_chars("aaa".chars(), U32StateBuilder::default()).unwrap());
+		assert_eq!(chain.estimated_minimal_size(), 3);
+		assert!(chain.is_minimal());
+
+		// redundant states are never overestimated.
+		let mut redundant: DFA<u32> = DFA::new(0);
+		redundant.add(0, ('a'..='a').into(), 1);
+		redundant.add(0, ('b'..='b').into(), 2);
+		redundant.add(1, ('c'..='c').into(), 3);
+		redundant.add(2, ('c'..='c').into(), 3);
+		redundant.add_final_state(3);
+		assert!(!redundant.is_minimal());
+		assert_eq!(redundant.estimated_minimal_size(), 3);
+	}
+
+	#[test]
+	fn lex() {
+		let mut state_builder = U32StateBuilder::default();
+		let keyword = NFA::from_chars("if".chars(), &mut state_builder).unwrap();
+		let ident = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['a'..='z']), Quantifier::Plus)],
+			&mut state_builder,
+		)
+		.unwrap();
+		let space = NFA::from_class_sequence(
+			&[(RangeSet::from_iter([' ']), Quantifier::Plus)],
+			&mut state_builder,
+		)
+		.unwrap();
+
+		// rules are listed by decreasing priority.
+		let names = ["keyword", "ident", "space"];
+		let rules = [keyword, ident, space];
+		let rule_of: BTreeMap<u32, usize> = rules
+			.iter()
+			.enumerate()
+			.flat_map(|(i, nfa)| nfa.final_states().iter().map(move |q| (*q, i)))
+			.collect();
+
+		let nfa = NFA::union_all(rules, &mut state_builder).unwrap();
+		let (dfa, tokens) = nfa.determinize_tagged(
+			|q| q.iter().copied().copied().collect::<Vec<_>>(),
+			|q| rule_of.get(q).copied(),
+		);
+		let payloads: BTreeMap<_, _> = tokens
+			.into_iter()
+			.map(|(q, rules)| (q, names[*rules.first().unwrap()]))
+			.collect();
+
+		let tokens: Vec<_> = dfa
+			.lex("if x", &payloads)
+			.filter(|token| !matches!(token, Ok((_, _, &"space"))))
+			.collect();
+		assert_eq!(tokens, [Ok((0, 2, &"keyword")), Ok((3, 4, &"ident"))]);
+
+		let tokens: Vec<_> = dfa.lex("iffy x1 y", &payloads).collect();
+		assert_eq!(
+			tokens,
+			[
+				Ok((0, 4, &"ident")),
+				Ok((4, 5, &"space")),
+				Ok((5, 6, &"ident")),
+				Err(LexError { offset: 6 }),
+			]
+		);
+	}
+
+	#[test]
+	fn reverse() {
+		let dfa = determinize(&NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap());
+		let reversed = dfa.reverse();
+
+		let reversed = determinize(&reversed);
+		assert!(reversed.accepts("cba"));
+		assert!(!reversed.accepts("abc"));
+
+		let expected =
+			determinize(&NFA::from_chars("cba".chars(), U32StateBuilder::default()).unwrap());
+		assert_eq!(
+			reversed.symmetric_difference(&expected).shortest_word(),
+			None
+		);
+	}
+
+	#[test]
+	fn prefix_automaton() {
+		let mut state_builder = U32StateBuilder::default();
+		let mut nfa = NFA::new();
+		for word in ["car", "cart", "cat", "dog"] {
+			let word = NFA::from_chars(word.chars(), &mut state_builder).unwrap();
+			nfa.union(word);
+		}
+
+		let dfa = determinize(&nfa).prefix_automaton();
+		for input in ["", "c", "ca", "car", "cart", "cat", "d", "do", "dog"] {
+			assert!(dfa.accepts(input), "{input:?}")
+		}
+		for input in ["a", "cb", "cats", "carts", "dot", "dogs"] {
+			assert!(!dfa.accepts(input), "{input:?}")
+		}
+	}
+
+	#[test]
+	fn predecessors() {
+		let dfa = determinize(
+			&NFA::from_class_sequence(
+				&[
+					(RangeSet::from_iter(['a'..='b']), Quantifier::Star),
+					(RangeSet::from_iter(['c']), Quantifier::Optional),
+				],
+				U32StateBuilder::default(),
+			)
+			.unwrap(),
+		);
+		let predecessors = dfa.predecessors();
+
+		for (q, q_transitions) in dfa.transitions() {
+			for (label, r) in q_transitions {
+				assert!(predecessors[r].contains(&(label, q)))
+			}
+		}
+
+		let count: usize = predecessors.values().map(Vec::len).sum();
+		assert_eq!(count, dfa.transitions().values().map(BTreeMap::len).sum());
+	}
+
+	#[test]
+	fn accepts_utf16() {
+		let nfa = NFA::from_chars("a😀".chars(), U32StateBuilder::default()).unwrap();
+		let dfa = determinize(&nfa);
+
+		let input: Vec<u16> = "a😀".encode_utf16().collect();
+		assert_eq!(input.len(), 3);
+		assert!(dfa.accepts_utf16(&input));
+		assert!(nfa.accepts_utf16(&input));
+
+		// truncated pair: lone high surrogate at the end.
+		assert!(!dfa.accepts_utf16(&input[..2]));
+		assert!(!nfa.accepts_utf16(&input[..2]));
+
+		// lone low surrogate.
+		let lone = [input[0], input[2]];
+		assert!(!dfa.accepts_utf16(&lone));
+		assert!(!nfa.accepts_utf16(&lone));
+	}
+
+	#[test]
+	fn parts_round_trip() {
+		let nfa = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		let dfa = determinize(&nfa);
+
+		let (initial_state, final_states, transitions) = dfa.clone().into_parts();
+		let map: BTreeMap<_, _> = transitions.into();
+		assert_eq!(map.len(), 4);
+
+		assert_eq!(
+			DFA::from_parts(initial_state, final_states, map.into()),
+			dfa
+		)
+	}
+
+	#[test]
+	fn bfs_with_paths() {
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('a'..='c').into(), 1);
+		dfa.add(0, ('x'..='x').into(), 2);
+		dfa.add(1, ('d'..='d').into(), 2);
+		dfa.add(2, ('e'..='e').into(), 3);
+		dfa.add(3, ('f'..='f').into(), 0);
+
+		let paths: Vec<_> = dfa.bfs_with_paths().collect();
+		assert_eq!(
+			paths,
+			[
+				(&0, String::new()),
+				(&1, "a".to_string()),
+				(&2, "x".to_string()),
+				(&3, "xe".to_string())
+			]
+		);
+
+		for (q, path) in paths {
+			assert_eq!(dfa.run(&path), Some(q))
+		}
+	}
+
+	#[test]
+	fn required_chars() {
+		let mut state_builder = U32StateBuilder::default();
+
+		let parts = [
+			any_star(&mut state_builder),
+			NFA::from_chars("foo".chars(), &mut state_builder).unwrap(),
+			any_star(&mut state_builder),
+		];
+		let nfa = NFA::concat_all(parts, &mut state_builder).unwrap();
+		let dfa = determinize(&nfa);
+
+		assert_eq!(dfa.required_chars(), RangeSet::from_iter(['f', 'o']))
+	}
+
+	#[test]
+	fn find_overlapping_iter() {
+		let nfa = NFA::from_chars("aa".chars(), U32StateBuilder::default()).unwrap();
+		let dfa = determinize(&nfa);
+
+		let matches: Vec<_> = dfa.find_overlapping_iter("aaa").collect();
+		assert_eq!(matches, [(0, 2), (1, 3)]);
+
+		let matches: Vec<_> = dfa
+			.find_overlapping_iter("aaaa")
+			.with_max_matches(2)
+			.collect();
+		assert_eq!(matches, [(0, 2), (1, 3)]);
+	}
+
+	#[test]
+	fn find_overlapping() {
+		let nfa = NFA::from_class_sequence(
+			&[
+				(any_char(), Quantifier::Star),
+				(RangeSet::from_iter(['a']), Quantifier::One),
+				(RangeSet::from_iter(['a']), Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa);
+
+		let ends: Vec<_> = dfa.find_overlapping("aaa").collect();
+		assert_eq!(ends, [2, 3]);
+
+		let ends: Vec<_> = dfa.find_overlapping("xaaébaa").collect();
+		assert_eq!(ends, [3, 8]);
+		assert_eq!(dfa.find_overlapping("abab").next(), None);
+	}
+
+	#[test]
+	fn are_distinguishable() {
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('a'..='a').into(), 1);
+		dfa.add(0, ('b'..='b').into(), 2);
+		dfa.add(1, ('c'..='c').into(), 3);
+		dfa.add(2, ('c'..='c').into(), 3);
+		dfa.add(3, ('d'..='d').into(), 4);
+		dfa.add_final_state(3);
+
+		assert!(dfa.are_distinguishable(&0, &3));
+		assert!(dfa.are_distinguishable(&0, &1));
+		assert!(!dfa.are_distinguishable(&1, &2));
+		assert!(!dfa.are_distinguishable(&1, &1));
+		assert!(dfa.are_distinguishable(&4, &2));
+		assert!(dfa.are_distinguishable(&4, &5));
+
+		// a missing transition is a transition to a dead state: `1`, `2` and
+		// `3` all recognize the empty language.
+		let mut dfa: DFA<u32> = DFA::new(0);
+		dfa.add(0, ('x'..='x').into(), 1);
+		dfa.add(0, ('y'..='y').into(), 2);
+		dfa.add(1, ('a'..='a').into(), 3);
+
+		for (p, q) in [(1, 2), (1, 3), (2, 3)] {
+			assert!(!dfa.are_distinguishable(&p, &q))
+		}
+
+		dfa.add_final_state(2);
+		assert!(!dfa.are_distinguishable(&1, &3));
+		for (p, q) in [(0, 1), (0, 2), (0, 3), (1, 2), (2, 3)] {
+			assert!(dfa.are_distinguishable(&p, &q))
+		}
+	}
+
+	#[test]
+	fn scan() {
+		let mut state_builder = U32StateBuilder::default();
+		let alts = ["a", "ab"].map(|s| NFA::from_chars(s.chars(), &mut state_builder).unwrap());
+		let nfa = NFA::union_all(alts, &mut state_builder).unwrap();
+		let dfa = determinize(&nfa);
+
+		let scan = dfa.scan("abc");
+		assert_eq!(scan.len(), 2);
+		assert_eq!(scan[0].0, 1);
+		assert_eq!(scan[1].0, 2);
+		assert_ne!(scan[0].1, scan[1].1);
+		assert!(dfa.scan("b").is_empty());
+	}
+
+	#[test]
+	fn run_classify() {
+		#[derive(Debug, PartialEq)]
+		enum Token {
+			Word,
+			Number,
+		}
+
+		let mut state_builder = U32StateBuilder::default();
+		let word = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['a'..='z']), Quantifier::Plus)],
+			&mut state_builder,
+		)
+		.unwrap();
+		let number = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['0'..='9']), Quantifier::Plus)],
+			&mut state_builder,
+		)
+		.unwrap();
+
+		let word_finals = word.final_states().clone();
+		let nfa = NFA::union_all([word, number], &mut state_builder).unwrap();
+
+		let mut ids = HashMap::new();
+		let mut payloads = BTreeMap::new();
+		let dfa = nfa.determinize(|q| {
+			let len = ids.len();
+			let id = *ids.entry(q.clone()).or_insert(len);
+
+			if q.iter().any(|q| nfa.is_final_state(q)) {
+				let token = if q.iter().any(|q| word_finals.contains(q)) {
+					Token::Word
+				} else {
+					Token::Number
+				};
+
+				payloads.insert(id, token);
+			}
+
+			id
+		});
+
+		assert_eq!(dfa.run_classify("123", &payloads), Some(&Token::Number));
+		assert_eq!(dfa.run_classify("abc", &payloads), Some(&Token::Word));
+		assert_eq!(dfa.run_classify("12a", &payloads), None);
+		assert_eq!(dfa.run_classify("", &payloads), None);
+	}
+
+	#[test]
+	fn restrict_alphabet() {
+		let nfa = NFA::from_class_sequence(
+			&[(
+				RangeSet::from_iter(['a'..='z', 'à'..='ÿ']),
+				Quantifier::Plus,
+			)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa);
+		assert!(dfa.run("café").map_or(false, |q| dfa.is_final_state(q)));
+
+		let ascii = dfa.restrict_alphabet(&RangeSet::from_iter(['\0'..='\x7f']));
+		assert!(ascii.run("cafe").map_or(false, |q| ascii.is_final_state(q)));
+		assert_eq!(ascii.run("café"), None);
+	}
+
+	#[test]
+	fn find() {
+		let nfa = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['a']), Quantifier::Plus)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa);
+
+		assert_eq!(dfa.find("xaaay"), Some(1..4));
+		assert_eq!(dfa.find("xy"), None);
+
+		let nfa = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['a']), Quantifier::Star)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa);
+
+		assert_eq!(dfa.find("xaaay"), Some(0..0));
+		assert_eq!(dfa.find("aay"), Some(0..2));
+	}
+
+	#[test]
+	fn find_with_mode() {
+		let nfa = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		let dfa = determinize(&nfa);
+
+		assert_eq!(dfa.find_with_mode("abcd", MatchMode::Full), None);
+		assert_eq!(dfa.find_with_mode("abc", MatchMode::Full), Some(0..3));
+		assert_eq!(dfa.find_with_mode("abcd", MatchMode::Prefix), Some(0..3));
+		assert_eq!(dfa.find_with_mode("xabcd", MatchMode::Prefix), None);
+		assert_eq!(dfa.find_with_mode("abcd", MatchMode::Anywhere), Some(0..3));
+		assert_eq!(dfa.find_with_mode("xabcd", MatchMode::Anywhere), Some(1..4));
+		assert_eq!(dfa.find_with_mode("abcd", MatchMode::Suffix), None);
+		assert_eq!(dfa.find_with_mode("xabc", MatchMode::Suffix), Some(1..4));
+	}
+
+	#[test]
+	fn accepts_suffix() {
+		let nfa = NFA::from_class_sequence(
+			&[
+				(RangeSet::from_iter(['a']), Quantifier::Plus),
+				(RangeSet::from_iter(['b']), Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = determinize(&nfa);
+
+		assert_eq!(dfa.accepts_suffix("xyaab"), Some(2));
+		assert_eq!(dfa.accepts_suffix("aabaab"), Some(3));
+		assert_eq!(dfa.accepts_suffix("éab"), Some(2));
+		assert_eq!(dfa.accepts_suffix("aaba"), None);
+		assert_eq!(dfa.accepts_suffix(""), None);
 	}
 }
@@ -0,0 +1,170 @@
+use std::{
+	collections::BTreeSet,
+	fmt::{self, Write},
+};
+
+use btree_range_map::RangeSet;
+
+use crate::{any_char, DFA, NFA};
+
+/// Writes the given character, escaped if it is a control character or a
+/// regular expression metacharacter.
+fn write_char(c: char, buffer: &mut String) {
+	match c {
+		'\\' | '[' | ']' | '^' | '-' | '.' | '(' | ')' | '{' | '}' | '*' | '+' | '?' | '|'
+		| '$' => {
+			buffer.push('\\');
+			buffer.push(c)
+		}
+		c if c.is_control() => buffer.extend(c.escape_default()),
+		c => buffer.push(c),
+	}
+}
+
+/// Formats the given character set as a regular expression class, such as
+/// `[0-9a-z\n]`.
+///
+/// Ranges of more than two characters are collapsed using `-`. Control
+/// characters and metacharacters are escaped. The set of all characters (see
+/// [`any_char`]) is formatted as `.`.
+pub fn format_charset(set: &RangeSet<char>) -> String {
+	if *set == any_char() {
+		return ".".to_string();
+	}
+
+	let mut result = String::from("[");
+
+	for range in set.iter() {
+		if let (Some(a), Some(b)) = (range.first(), range.last()) {
+			write_char(a, &mut result);
+
+			if range.len() > 2 {
+				result.push('-');
+			}
+
+			if a != b {
+				write_char(b, &mut result)
+			}
+		}
+	}
+
+	result.push(']');
+	result
+}
+
+/// Writes the header line of a state, marked with `->` if it is initial and
+/// `*` if it is final.
+fn fmt_state<Q: fmt::Display>(
+	q: &Q,
+	is_initial: bool,
+	is_final: bool,
+	f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+	let initial = if is_initial { "->" } else { "  " };
+	let final_ = if is_final { '*' } else { ' ' };
+	writeln!(f, "{initial}{final_} {q}")
+}
+
+impl<Q: Ord + fmt::Display> fmt::Display for NFA<Q> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut states: BTreeSet<&Q> = self.transitions().map(|(q, _)| q).collect();
+		states.extend(self.initial_states());
+		states.extend(self.final_states());
+
+		for q in states {
+			fmt_state(q, self.is_initial_state(q), self.is_final_state(q), f)?;
+
+			for (label, targets) in self.successors(q) {
+				for r in targets {
+					f.write_char('\t')?;
+					match label {
+						Some(label) => f.write_str(&format_charset(label))?,
+						None => f.write_char('ε')?,
+					}
+					writeln!(f, " => {r}")?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl<Q: Ord + fmt::Display> fmt::Display for DFA<Q> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut states: BTreeSet<&Q> = BTreeSet::new();
+		states.insert(self.initial_state());
+		states.extend(self.final_states());
+		for (q, q_transitions) in self.transitions() {
+			states.insert(q);
+			states.extend(q_transitions.values());
+		}
+
+		for q in states {
+			fmt_state(q, self.is_initial_state(q), self.is_final_state(q), f)?;
+
+			for (range, r) in self.successors(q) {
+				let label: RangeSet<char> = [*range].into_iter().collect();
+				writeln!(f, "\t{} => {r}", format_charset(&label))?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::nfa::{Quantifier, U32StateBuilder};
+
+	#[test]
+	fn display_nfa() {
+		let mut nfa: NFA = NFA::from_class_sequence(
+			&[
+				(['a'..='c'].into_iter().collect(), Quantifier::One),
+				(['x'].into_iter().collect(), Quantifier::Plus),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		nfa.add(0, None, 2);
+
+		assert_eq!(
+			nfa.to_string(),
+			"->  0\n\tε => 2\n\t[a-c] => 1\n    1\n\t[x] => 2\n  * 2\n\t[x] => 2\n"
+		)
+	}
+
+	#[test]
+	fn display_dfa() {
+		let nfa: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+		let dfa = nfa.determinize(|q| q.iter().map(|q| q.to_string()).collect::<String>());
+
+		assert_eq!(
+			dfa.to_string(),
+			"->  0\n\t[a] => 1\n    1\n\t[b] => 2\n  * 2\n"
+		)
+	}
+
+	#[test]
+	fn format_charset() {
+		let mut set = RangeSet::new();
+		set.insert('a'..='z');
+		set.insert('0'..='9');
+		set.insert('_');
+		assert_eq!(super::format_charset(&set), "[0-9_a-z]");
+
+		let set: RangeSet<char> = ['x', 'y', '-', ']'].into_iter().collect();
+		assert_eq!(super::format_charset(&set), "[\\-\\]xy]");
+
+		let set: RangeSet<char> = ['\n'].into_iter().collect();
+		assert_eq!(super::format_charset(&set), "[\\n]");
+
+		let set: RangeSet<char> = ['\x07'].into_iter().collect();
+		assert_eq!(super::format_charset(&set), "[\\u{7}]");
+
+		assert_eq!(super::format_charset(&any_char()), ".");
+		assert_eq!(super::format_charset(&RangeSet::new()), "[]");
+	}
+}
@@ -0,0 +1,205 @@
+//! Conversion from the [`regex_syntax`] high-level intermediate
+//! representation (HIR).
+use btree_range_map::RangeSet;
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+use crate::nfa::{StateBuilder, NFA};
+
+/// Error raised when building an automaton from a HIR.
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError<E> {
+	/// The HIR contains a construct that cannot be expressed by a finite
+	/// automaton over characters.
+	#[error("unsupported construct: {0}")]
+	Unsupported(&'static str),
+
+	/// The state builder failed.
+	#[error("state builder failed")]
+	StateBuilder(E),
+}
+
+impl<Q: Clone + Ord> NFA<Q> {
+	/// Builds an automaton recognizing the language of the given
+	/// [`regex_syntax`] HIR.
+	///
+	/// Literals, classes, concatenations, alternations, repetitions and
+	/// capture groups are supported. Look-around assertions (such as `^` or
+	/// `\b`) and non-UTF-8 byte classes or literals are not.
+	pub fn from_hir<S: StateBuilder<Q>>(
+		hir: &Hir,
+		state_builder: &mut S,
+	) -> Result<Self, BuildError<S::Error>> {
+		let mut result = NFA::new();
+		let (a, b) = build_hir(hir, state_builder, &mut result)?;
+		result.add_initial_state(a);
+		result.add_final_state(b);
+		Ok(result)
+	}
+}
+
+/// Creates a new state in `nfa`.
+fn new_state<Q, S: StateBuilder<Q>>(
+	state_builder: &mut S,
+	nfa: &mut NFA<Q>,
+) -> Result<Q, BuildError<S::Error>> {
+	state_builder
+		.next_state(nfa)
+		.map_err(BuildError::StateBuilder)
+}
+
+/// Adds the automaton of `hir` into `nfa`, returning its start and end
+/// states.
+fn build_hir<Q: Clone + Ord, S: StateBuilder<Q>>(
+	hir: &Hir,
+	state_builder: &mut S,
+	nfa: &mut NFA<Q>,
+) -> Result<(Q, Q), BuildError<S::Error>> {
+	match hir.kind() {
+		HirKind::Empty => {
+			let a = new_state(state_builder, nfa)?;
+			Ok((a.clone(), a))
+		}
+		HirKind::Literal(literal) => {
+			let s = std::str::from_utf8(&literal.0)
+				.map_err(|_| BuildError::Unsupported("non UTF-8 literal"))?;
+
+			let a = new_state(state_builder, nfa)?;
+			let mut b = a.clone();
+			for c in s.chars() {
+				let c_end = new_state(state_builder, nfa)?;
+				nfa.add(b, Some(RangeSet::from_iter([c])), c_end.clone());
+				b = c_end
+			}
+
+			Ok((a, b))
+		}
+		HirKind::Class(class) => {
+			let mut label = RangeSet::new();
+			match class {
+				Class::Unicode(class) => {
+					for range in class.iter() {
+						label.insert(range.start()..=range.end())
+					}
+				}
+				Class::Bytes(class) => {
+					for range in class.iter() {
+						if !range.end().is_ascii() {
+							return Err(BuildError::Unsupported("non UTF-8 byte class"));
+						}
+
+						label.insert(range.start() as char..=range.end() as char)
+					}
+				}
+			}
+
+			let a = new_state(state_builder, nfa)?;
+			let b = new_state(state_builder, nfa)?;
+			nfa.add(a.clone(), Some(label), b.clone());
+			Ok((a, b))
+		}
+		HirKind::Look(_) => Err(BuildError::Unsupported("look-around assertion")),
+		HirKind::Repetition(repetition) => {
+			let a = new_state(state_builder, nfa)?;
+			let mut b = a.clone();
+
+			for _ in 0..repetition.min {
+				let (sub_a, sub_b) = build_hir(&repetition.sub, state_builder, nfa)?;
+				nfa.add(b, None, sub_a);
+				b = sub_b
+			}
+
+			match repetition.max {
+				Some(max) => {
+					let end = new_state(state_builder, nfa)?;
+					nfa.add(b.clone(), None, end.clone());
+
+					for _ in repetition.min..max {
+						let (sub_a, sub_b) = build_hir(&repetition.sub, state_builder, nfa)?;
+						nfa.add(b, None, sub_a);
+						nfa.add(sub_b.clone(), None, end.clone());
+						b = sub_b
+					}
+
+					Ok((a, end))
+				}
+				None => {
+					let (sub_a, sub_b) = build_hir(&repetition.sub, state_builder, nfa)?;
+					let end = new_state(state_builder, nfa)?;
+					nfa.add(b.clone(), None, sub_a.clone());
+					nfa.add(sub_b.clone(), None, sub_a);
+					nfa.add(b, None, end.clone());
+					nfa.add(sub_b, None, end.clone());
+					Ok((a, end))
+				}
+			}
+		}
+		HirKind::Capture(capture) => build_hir(&capture.sub, state_builder, nfa),
+		HirKind::Concat(hirs) => {
+			let a = new_state(state_builder, nfa)?;
+			let mut b = a.clone();
+
+			for hir in hirs {
+				let (sub_a, sub_b) = build_hir(hir, state_builder, nfa)?;
+				nfa.add(b, None, sub_a);
+				b = sub_b
+			}
+
+			Ok((a, b))
+		}
+		HirKind::Alternation(hirs) => {
+			let a = new_state(state_builder, nfa)?;
+			let b = new_state(state_builder, nfa)?;
+
+			for hir in hirs {
+				let (sub_a, sub_b) = build_hir(hir, state_builder, nfa)?;
+				nfa.add(a.clone(), None, sub_a);
+				nfa.add(sub_b, None, b.clone());
+			}
+
+			Ok((a, b))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{nfa::U32StateBuilder, DynMatcher};
+
+	fn build(pattern: &str) -> Result<NFA, BuildError<crate::nfa::TooManyStates>> {
+		let hir = regex_syntax::parse(pattern).unwrap();
+		NFA::from_hir(&hir, &mut U32StateBuilder::default())
+	}
+
+	#[test]
+	fn from_hir() {
+		let vectors: &[(&str, &[&str], &[&str])] = &[
+			("abc", &["abc"], &["", "ab", "abcd"]),
+			("a(b|cd)*e", &["ae", "abe", "acdbe"], &["a", "ace", "abd"]),
+			("[a-c]{2,3}", &["ab", "cba"], &["a", "abcd", "ad"]),
+			("x+y?", &["x", "xxy"], &["", "y", "xyy"]),
+			("(?i)k", &["k", "K", "\u{212a}"], &["l"]),
+			("é.", &["éa", "é\u{10ffff}"], &["é", "é\n"]),
+			("a{0}", &[""], &["a"]),
+		];
+
+		for (pattern, positive, negative) in vectors {
+			let nfa = build(pattern).unwrap();
+
+			for input in *positive {
+				assert!(nfa.is_match(input), "{pattern} on {input:?}")
+			}
+
+			for input in *negative {
+				assert!(!nfa.is_match(input), "{pattern} on {input:?}")
+			}
+		}
+	}
+
+	#[test]
+	fn unsupported() {
+		for pattern in ["^a", "a$", r"\bword"] {
+			assert!(matches!(build(pattern), Err(BuildError::Unsupported(_))))
+		}
+	}
+}
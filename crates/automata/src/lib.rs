@@ -4,6 +4,9 @@
 //! regular expressions.
 //!
 //! [`ere`]: <https://github.com/timothee-haudebourg/ere-rs>
+use std::collections::{BTreeMap, BTreeSet};
+
+use btree_range_map::RangeMap;
 pub use btree_range_map::{AnyRange, RangeSet};
 
 pub mod nfa;
@@ -12,6 +15,31 @@ pub use nfa::NFA;
 pub mod dfa;
 pub use dfa::DFA;
 
+pub mod table;
+pub use table::{DenseDfa, DfaTable, FlatDfa};
+
+pub mod transducer;
+pub use transducer::Transducer;
+
+pub mod literal;
+pub use literal::Literal;
+
+pub mod matcher;
+pub use matcher::{ByteMatcher, DynMatcher, MatchMode, Matcher};
+
+pub mod classes;
+
+mod threads;
+
+#[cfg(feature = "regex-syntax")]
+pub mod hir;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+mod display;
+pub use display::format_charset;
+
 pub fn any_char() -> RangeSet<char> {
 	let mut set = RangeSet::new();
 	set.insert('\u{0}'..='\u{d7ff}');
@@ -30,6 +58,97 @@ pub fn charset_intersection(a: &RangeSet<char>, b: &RangeSet<char>) -> RangeSet<
 	result
 }
 
+/// Computes the intersection of all the given character sets.
+///
+/// The intersection of no set at all is [`any_char`], the identity of the
+/// intersection.
+pub fn charset_intersection_many<'a>(
+	sets: impl IntoIterator<Item = &'a RangeSet<char>>,
+) -> RangeSet<char> {
+	let mut result = any_char();
+
+	for set in sets {
+		for r in set.gaps() {
+			result.remove(r.cloned());
+		}
+	}
+
+	result
+}
+
+/// Computes the union of all the given character sets.
+///
+/// The union of no set at all is the empty set, the identity of the union.
+pub fn charset_union_many<'a>(
+	sets: impl IntoIterator<Item = &'a RangeSet<char>>,
+) -> RangeSet<char> {
+	let mut result = RangeSet::new();
+
+	for set in sets {
+		for r in set.iter() {
+			result.insert(*r);
+		}
+	}
+
+	result
+}
+
+/// Splits the given character sets into disjoint blocks.
+///
+/// Returns the coarsest partition of the union of `sets` such that each input
+/// set is a union of blocks: two characters are in the same block if and only
+/// if they belong to the same input sets. Blocks are sorted by their first
+/// character. This is useful to compute disjoint transition labels from
+/// overlapping character classes.
+///
+/// ```
+/// use iregex_automata::{split_charsets, RangeSet};
+///
+/// let blocks = split_charsets(&[
+///     RangeSet::from_iter(['a'..='c']),
+///     RangeSet::from_iter(['b'..='d']),
+/// ]);
+///
+/// assert_eq!(blocks, [
+///     RangeSet::from_iter(['a']),
+///     RangeSet::from_iter(['b'..='c']),
+///     RangeSet::from_iter(['d']),
+/// ]);
+/// ```
+pub fn split_charsets(sets: &[RangeSet<char>]) -> Vec<RangeSet<char>> {
+	let mut map: RangeMap<char, BTreeSet<usize>> = RangeMap::new();
+
+	for (i, set) in sets.iter().enumerate() {
+		for range in set.iter() {
+			map.update(*range, |current: Option<&BTreeSet<usize>>| {
+				let mut current = current.cloned().unwrap_or_default();
+				current.insert(i);
+				Some(current)
+			})
+		}
+	}
+
+	let mut blocks: BTreeMap<BTreeSet<usize>, RangeSet<char>> = BTreeMap::new();
+	for (range, signature) in map {
+		blocks.entry(signature).or_default().insert(range)
+	}
+
+	let mut result: Vec<_> = blocks.into_values().collect();
+	result.sort_by_key(|block| block.iter().next().and_then(AnyRange::first));
+	result
+}
+
+/// Returns an iterator over every character of the given set, in order.
+///
+/// Surrogate code points (between `\u{d7ff}` and `\u{e000}`) are not
+/// characters and are skipped. Iterating over a large set is expensive:
+/// [`any_char`] contains more than a million characters.
+pub fn charset_chars(set: &RangeSet<char>) -> impl '_ + Iterator<Item = char> {
+	set.iter()
+		.filter_map(|range| Some(range.first()?..=range.last()?))
+		.flatten()
+}
+
 /// Deterministic or non-deterministic automaton.
 pub trait Automaton<T> {
 	type State<'a>
@@ -42,7 +161,240 @@ pub trait Automaton<T> {
 		&'a self,
 		current_state: Self::State<'a>,
 		token: T,
-	) -> Option<Self::State<'_>>;
+	) -> Option<Self::State<'a>>;
 
 	fn is_final_state<'a>(&'a self, state: &Self::State<'a>) -> bool;
+
+	/// Turns the given state back into the initial state.
+	///
+	/// This is equivalent to [`Self::initial_state`], but allows automata
+	/// with heap-allocated states, such as [`NFA`], to reuse the allocations
+	/// of `state` instead of allocating a new state.
+	fn reset_state<'a>(&'a self, state: Self::State<'a>) -> Option<Self::State<'a>> {
+		std::mem::drop(state);
+		self.initial_state()
+	}
+
+	/// Reads every token of the input from the initial state, returning the
+	/// state reached at the end.
+	///
+	/// Returns `None` if the automaton dies before the end of the input, or
+	/// has no initial state. The input is recognized if the returned state is
+	/// final.
+	fn run<I: IntoIterator<Item = T>>(&self, input: I) -> Option<Self::State<'_>> {
+		let mut state = self.initial_state()?;
+
+		for token in input {
+			state = self.next_state(state, token)?
+		}
+
+		Some(state)
+	}
+
+	/// Returns the length of the longest prefix of the input recognized by the
+	/// automaton.
+	///
+	/// The input is given as `(offset, token)` pairs, where `offset` is the
+	/// offset right *after* `token` in the input. The returned value is the
+	/// offset after the last token of the longest recognized prefix, `0` if
+	/// only the empty prefix is recognized, and `None` if no prefix is
+	/// recognized. Reading stops as soon as the automaton dies.
+	///
+	/// For a `&str`, byte offsets can be computed using
+	/// [`str::char_indices`]:
+	///
+	/// ```
+	/// use iregex_automata::{nfa::U32StateBuilder, Automaton, NFA};
+	///
+	/// let nfa: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+	/// let input = "abc";
+	/// let indexed = input.char_indices().map(|(i, c)| (i + c.len_utf8(), c));
+	/// assert_eq!(nfa.longest_match_len(indexed), Some(2));
+	/// ```
+	fn longest_match_len<I: IntoIterator<Item = (usize, T)>>(&self, indexed: I) -> Option<usize> {
+		let mut state = self.initial_state()?;
+		let mut result = None;
+
+		if self.is_final_state(&state) {
+			result = Some(0)
+		}
+
+		for (offset, token) in indexed {
+			match self.next_state(state, token) {
+				Some(next_state) => state = next_state,
+				None => break,
+			}
+
+			if self.is_final_state(&state) {
+				result = Some(offset)
+			}
+		}
+
+		result
+	}
+
+	/// Reads the given token from the given state.
+	///
+	/// This is similar to [`Self::next_state`], but returns the rejected token
+	/// when the automaton dies, which is useful for reporting errors.
+	fn step<'a>(&'a self, state: Self::State<'a>, token: T) -> StepResult<Self::State<'a>, T>
+	where
+		T: Clone,
+	{
+		match self.next_state(state, token.clone()) {
+			Some(state) => StepResult::Alive(state),
+			None => StepResult::Dead { at_token: token },
+		}
+	}
+}
+
+/// Result of [`Automaton::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult<S, T> {
+	/// The token was consumed and the automaton is still alive.
+	Alive(S),
+
+	/// The automaton has no transition for the token.
+	Dead {
+		/// The rejected token.
+		at_token: T,
+	},
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn longest_match_len() {
+		fn indexed(input: &str) -> impl '_ + Iterator<Item = (usize, char)> {
+			input.char_indices().map(|(i, c)| (i + c.len_utf8(), c))
+		}
+
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(RangeSet::from_iter(['a']), nfa::Quantifier::One),
+				(RangeSet::from_iter(['é']), nfa::Quantifier::Star),
+			],
+			nfa::U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = nfa.determinize(|q| q.iter().copied().copied().collect::<Vec<_>>());
+
+		assert_eq!(nfa.longest_match_len(indexed("aééb")), Some(5));
+		assert_eq!(dfa.longest_match_len(indexed("aééb")), Some(5));
+		assert_eq!(nfa.longest_match_len(indexed("a")), Some(1));
+		assert_eq!(dfa.longest_match_len(indexed("a")), Some(1));
+		assert_eq!(nfa.longest_match_len(indexed("ba")), None);
+		assert_eq!(dfa.longest_match_len(indexed("ba")), None);
+
+		let empty: NFA = NFA::from_chars([], nfa::U32StateBuilder::default()).unwrap();
+		assert_eq!(empty.longest_match_len(indexed("abc")), Some(0));
+	}
+
+	#[test]
+	fn run() {
+		let nfa: NFA = NFA::from_chars("ab".chars(), nfa::U32StateBuilder::default()).unwrap();
+		let dfa = nfa.determinize(|q| q.iter().copied().copied().collect::<Vec<_>>());
+
+		let state = Automaton::run(&nfa, "ab".chars()).unwrap();
+		assert!(Automaton::is_final_state(&nfa, &state));
+		let state = Automaton::run(&nfa, "a".chars()).unwrap();
+		assert!(!Automaton::is_final_state(&nfa, &state));
+		assert!(Automaton::run(&nfa, "ba".chars()).is_none());
+
+		let state = Automaton::run(&dfa, "ab".chars()).unwrap();
+		assert!(dfa.is_final_state(state));
+		assert_eq!(Automaton::run(&dfa, "a".chars()), dfa.run("a"));
+		assert!(Automaton::run(&dfa, "abc".chars()).is_none());
+	}
+
+	#[test]
+	fn charset_many() {
+		let sets = [
+			RangeSet::from_iter(['a'..='m', 'x'..='z']),
+			RangeSet::from_iter(['f'..='y']),
+			RangeSet::from_iter(['0'..='9', 'h'..='z']),
+		];
+		let [a, b, c] = &sets;
+
+		assert_eq!(
+			charset_intersection_many(&sets),
+			RangeSet::from_iter(['h'..='m', 'x'..='y'])
+		);
+		assert_eq!(
+			charset_intersection_many(&sets),
+			charset_intersection(&charset_intersection(a, b), c)
+		);
+		assert_eq!(
+			charset_intersection_many(&sets),
+			charset_intersection(a, &charset_intersection(b, c))
+		);
+
+		assert_eq!(
+			charset_union_many(&sets),
+			RangeSet::from_iter(['0'..='9', 'a'..='z'])
+		);
+		assert_eq!(
+			charset_union_many(&sets),
+			charset_union_many([&charset_union_many([a, b]), c])
+		);
+		assert_eq!(
+			charset_union_many(&sets),
+			charset_union_many([a, &charset_union_many([b, c])])
+		);
+
+		assert_eq!(charset_intersection_many([]), any_char());
+		assert!(charset_union_many([]).is_empty());
+	}
+
+	#[test]
+	fn split_charsets() {
+		let blocks = super::split_charsets(&[
+			RangeSet::from_iter(['a'..='c']),
+			RangeSet::from_iter(['b'..='d']),
+		]);
+		assert_eq!(
+			blocks,
+			[
+				RangeSet::from_iter(['a']),
+				RangeSet::from_iter(['b'..='c']),
+				RangeSet::from_iter(['d']),
+			]
+		);
+
+		// non-contiguous block.
+		let blocks = super::split_charsets(&[
+			RangeSet::from_iter(['a'..='c']),
+			RangeSet::from_iter(['b']),
+			RangeSet::from_iter(['x']),
+		]);
+		assert_eq!(
+			blocks,
+			[
+				RangeSet::from_iter(['a', 'c']),
+				RangeSet::from_iter(['b']),
+				RangeSet::from_iter(['x']),
+			]
+		);
+
+		assert!(super::split_charsets(&[]).is_empty());
+		assert_eq!(super::split_charsets(&[any_char()]), [any_char()]);
+	}
+
+	#[test]
+	fn charset_chars() {
+		let mut set = RangeSet::new();
+		set.insert('a'..='c');
+		set.insert('\u{d7fe}'..='\u{e001}');
+
+		let chars: Vec<_> = super::charset_chars(&set).collect();
+		assert_eq!(
+			chars,
+			['a', 'b', 'c', '\u{d7fe}', '\u{d7ff}', '\u{e000}', '\u{e001}']
+		);
+
+		assert_eq!(super::charset_chars(&RangeSet::new()).count(), 0);
+		assert_eq!(super::charset_chars(&any_char()).count(), 0x110000 - 0x800);
+	}
 }
@@ -0,0 +1,85 @@
+use crate::{Automaton, NFA};
+
+/// Automaton recognizing a single constant string.
+///
+/// This is a lightweight alternative to [`NFA`] and [`DFA`](crate::DFA) for
+/// patterns without any operator. The state is the (byte) position in the
+/// string of the next expected character, so that stepping is a simple
+/// comparison.
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Literal {
+	s: String,
+}
+
+impl Literal {
+	/// Creates a new automaton recognizing exactly `s`.
+	pub fn new(s: impl Into<String>) -> Self {
+		Self { s: s.into() }
+	}
+
+	/// Creates a new automaton recognizing the same string as `nfa`, if it
+	/// recognizes exactly one string.
+	///
+	/// See [`NFA::to_singleton`].
+	pub fn from_nfa<Q: Ord>(nfa: &NFA<Q>) -> Option<Self> {
+		nfa.to_singleton().map(Self::new)
+	}
+
+	/// Returns the recognized string.
+	pub fn as_str(&self) -> &str {
+		&self.s
+	}
+
+	/// Consumes the automaton and returns the recognized string.
+	pub fn into_string(self) -> String {
+		self.s
+	}
+}
+
+impl Automaton<char> for Literal {
+	type State<'a> = usize;
+
+	fn initial_state(&self) -> Option<usize> {
+		Some(0)
+	}
+
+	fn next_state(&self, q: usize, token: char) -> Option<usize> {
+		if self.s[q..].starts_with(token) {
+			Some(q + token.len_utf8())
+		} else {
+			None
+		}
+	}
+
+	fn is_final_state(&self, q: &usize) -> bool {
+		*q == self.s.len()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{nfa::U32StateBuilder, DynMatcher};
+
+	#[test]
+	fn literal() {
+		let literal = Literal::new("aé!");
+
+		assert!(literal.is_match("aé!"));
+		for input in ["", "a", "aé", "aé!!", "aè!", "baé!"] {
+			assert!(!literal.is_match(input), "{input:?}")
+		}
+
+		assert_eq!(literal.run("aé".chars()), Some(3));
+		assert_eq!(literal.run("ab".chars()), None);
+		assert_eq!(literal.find("xaé!aé!"), Some(1..5));
+		assert!(Literal::default().is_match(""));
+	}
+
+	#[test]
+	fn from_nfa() {
+		let nfa: NFA = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		assert_eq!(Literal::from_nfa(&nfa), Some(Literal::new("abc")));
+		assert_eq!(Literal::from_nfa(&NFA::<u32>::new()), None);
+	}
+}
@@ -0,0 +1,421 @@
+use std::{marker::PhantomData, ops::Range};
+
+use crate::{Automaton, DFA};
+
+/// Incremental matcher.
+///
+/// Runs an automaton token by token, keeping track of the current state.
+pub struct Matcher<'a, A: Automaton<T>, T> {
+	aut: &'a A,
+	state: Option<A::State<'a>>,
+	len: usize,
+	t: PhantomData<T>,
+}
+
+impl<'a, A: Automaton<T>, T> Matcher<'a, A, T> {
+	/// Creates a new matcher starting in the initial state of the given
+	/// automaton.
+	pub fn new(aut: &'a A) -> Self {
+		Self {
+			aut,
+			state: aut.initial_state(),
+			len: 0,
+			t: PhantomData,
+		}
+	}
+
+	/// Returns the automaton run by this matcher.
+	pub fn automaton(&self) -> &'a A {
+		self.aut
+	}
+
+	/// Consumes the matcher and returns the automaton it runs.
+	pub fn into_automaton(self) -> &'a A {
+		self.aut
+	}
+
+	/// Feeds the given token to the automaton.
+	///
+	/// Returns `true` if the automaton is still alive after reading the token,
+	/// meaning that more input may lead to a match. Once dead, the matcher
+	/// stays dead until [`Self::reset`] is called.
+	pub fn feed(&mut self, token: T) -> bool {
+		if let Some(state) = self.state.take() {
+			self.state = self.aut.next_state(state, token)
+		}
+
+		self.len += 1;
+		self.is_alive()
+	}
+
+	/// Checks if the automaton is still alive.
+	pub fn is_alive(&self) -> bool {
+		self.state.is_some()
+	}
+
+	/// Checks if the tokens fed so far are recognized by the automaton.
+	pub fn is_match(&self) -> bool {
+		match &self.state {
+			Some(state) => self.aut.is_final_state(state),
+			None => false,
+		}
+	}
+
+	/// Returns the number of tokens fed since the matcher was created or last
+	/// reset.
+	pub fn matched_len(&self) -> usize {
+		self.len
+	}
+
+	/// Puts the matcher back in the initial state of the automaton.
+	///
+	/// If the matcher is still alive, its current state is recycled using
+	/// [`Automaton::reset_state`]: for an [`NFA`](crate::NFA), the sets of
+	/// states and the work stack are cleared and refilled, keeping their
+	/// allocations. A dead matcher has no state left to recycle, so a new
+	/// initial state is created.
+	pub fn reset(&mut self) {
+		self.state = match self.state.take() {
+			Some(state) => self.aut.reset_state(state),
+			None => self.aut.initial_state(),
+		};
+		self.len = 0
+	}
+}
+
+impl<'a, A: Automaton<char>> Matcher<'a, A, char> {
+	/// Resets the matcher and runs it on `input` from the (byte) position
+	/// `start`, returning the end position of the longest match starting at
+	/// `start`.
+	pub(crate) fn longest_match_from(&mut self, input: &str, start: usize) -> Option<usize> {
+		self.reset();
+
+		let mut end = None;
+		if self.is_match() {
+			end = Some(start)
+		}
+
+		for (i, c) in input[start..].char_indices() {
+			if !self.feed(c) {
+				break;
+			}
+
+			if self.is_match() {
+				end = Some(start + i + c.len_utf8())
+			}
+		}
+
+		end
+	}
+
+	/// Feeds every character of the given string to the automaton.
+	///
+	/// Returns `true` if the automaton is still alive after reading the
+	/// string.
+	pub fn feed_str(&mut self, s: &str) -> bool {
+		for c in s.chars() {
+			self.feed(c);
+		}
+
+		self.is_alive()
+	}
+}
+
+/// Incremental matcher reading UTF-8 encoded bytes.
+///
+/// Bytes can be fed in arbitrary chunks, even splitting the encoding of a
+/// character: incomplete sequences are buffered until the next call to
+/// [`Self::feed`]. The underlying automaton is advanced once per decoded
+/// character. Invalid UTF-8 kills the matcher.
+pub struct ByteMatcher<'a, Q> {
+	dfa: &'a DFA<Q>,
+	state: Option<&'a Q>,
+	buffer: [u8; 4],
+	buffer_len: usize,
+}
+
+impl<'a, Q: Ord> ByteMatcher<'a, Q> {
+	/// Creates a new matcher starting in the initial state of the given
+	/// automaton.
+	pub fn new(dfa: &'a DFA<Q>) -> Self {
+		Self {
+			dfa,
+			state: Some(dfa.initial_state()),
+			buffer: [0; 4],
+			buffer_len: 0,
+		}
+	}
+
+	/// Feeds the given bytes to the automaton.
+	///
+	/// Returns `true` if the automaton is still alive after reading the bytes.
+	pub fn feed(&mut self, bytes: &[u8]) -> bool {
+		for &b in bytes {
+			if self.state.is_none() {
+				break;
+			}
+
+			if self.buffer_len > 0 && b & 0xc0 != 0x80 {
+				// expected a continuation byte.
+				self.state = None;
+				break;
+			}
+
+			self.buffer[self.buffer_len] = b;
+			self.buffer_len += 1;
+
+			let expected_len = match self.buffer[0] {
+				0x00..=0x7f => 1,
+				0xc0..=0xdf => 2,
+				0xe0..=0xef => 3,
+				0xf0..=0xf7 => 4,
+				_ => {
+					self.state = None;
+					break;
+				}
+			};
+
+			if self.buffer_len == expected_len {
+				self.state = std::str::from_utf8(&self.buffer[..self.buffer_len])
+					.ok()
+					.and_then(|s| s.chars().next())
+					.zip(self.state)
+					.and_then(|(c, q)| self.dfa.next_state(q, c));
+				self.buffer_len = 0
+			}
+		}
+
+		self.is_alive()
+	}
+
+	/// Checks if the automaton is still alive.
+	pub fn is_alive(&self) -> bool {
+		self.state.is_some()
+	}
+
+	/// Checks if the bytes fed so far are recognized by the automaton.
+	///
+	/// Returns `false` if the bytes end with an incomplete UTF-8 sequence.
+	pub fn is_match(&self) -> bool {
+		self.buffer_len == 0 && self.state.map_or(false, |q| self.dfa.is_final_state(q))
+	}
+
+	/// Puts the matcher back in the initial state of the automaton, dropping
+	/// any buffered byte.
+	pub fn reset(&mut self) {
+		self.state = Some(self.dfa.initial_state());
+		self.buffer_len = 0
+	}
+}
+
+/// Anchoring of a match, used by [`DynMatcher::find_with_mode`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchMode {
+	/// The match must span the whole input (as with `^pat$`).
+	Full,
+
+	/// The match must start at the beginning of the input (as with `^pat`).
+	Prefix,
+
+	/// The match must end at the end of the input (as with `pat$`).
+	Suffix,
+
+	/// The match can start anywhere in the input (as with `pat`).
+	#[default]
+	Anywhere,
+}
+
+/// Object-safe matching interface.
+///
+/// The [`Automaton`] trait is not object-safe because of its generic
+/// associated state type. This trait provides a simpler interface that can be
+/// used to store heterogeneous automata as `Box<dyn DynMatcher>`. It is
+/// implemented by every [`Automaton<char>`].
+pub trait DynMatcher {
+	/// Checks if the whole input is recognized.
+	fn is_match(&self, input: &str) -> bool;
+
+	/// Finds the leftmost-longest match in `input`, returning its (byte)
+	/// range.
+	///
+	/// The match starting at the smallest position is selected, and among
+	/// those the longest one. In particular, if the automaton recognizes the
+	/// empty string, a zero-width match at position `0` is returned unless a
+	/// longer match starts at position `0`.
+	///
+	/// The automaton is run from each position until a match is found, using
+	/// a single [`Matcher`] reset between positions, so the worst-case
+	/// complexity is quadratic in the length of `input`.
+	fn find(&self, input: &str) -> Option<Range<usize>>;
+
+	/// Finds a match in `input` according to the given [`MatchMode`],
+	/// returning its (byte) range.
+	///
+	/// With [`MatchMode::Full`] the whole input must be recognized, with
+	/// [`MatchMode::Prefix`] the longest recognized prefix is returned, with
+	/// [`MatchMode::Suffix`] the longest recognized suffix, and with
+	/// [`MatchMode::Anywhere`] this is the same as [`Self::find`].
+	///
+	/// Suffixes are searched by running the automaton from each position
+	/// until it recognizes the rest of the input, so the worst-case
+	/// complexity is quadratic. See [`NFA::anchored_end`](crate::NFA::anchored_end)
+	/// to read the input backward instead.
+	fn find_with_mode(&self, input: &str, mode: MatchMode) -> Option<Range<usize>>;
+}
+
+impl<A: Automaton<char>> DynMatcher for A {
+	fn is_match(&self, input: &str) -> bool {
+		self.run(input.chars())
+			.map_or(false, |state| self.is_final_state(&state))
+	}
+
+	fn find(&self, input: &str) -> Option<Range<usize>> {
+		let mut matcher = Matcher::new(self);
+
+		input
+			.char_indices()
+			.map(|(i, _)| i)
+			.chain(Some(input.len()))
+			.find_map(|start| Some(start..matcher.longest_match_from(input, start)?))
+	}
+
+	fn find_with_mode(&self, input: &str, mode: MatchMode) -> Option<Range<usize>> {
+		match mode {
+			MatchMode::Full => {
+				if DynMatcher::is_match(self, input) {
+					Some(0..input.len())
+				} else {
+					None
+				}
+			}
+			MatchMode::Prefix => Matcher::new(self)
+				.longest_match_from(input, 0)
+				.map(|end| 0..end),
+			MatchMode::Suffix => {
+				let mut matcher = Matcher::new(self);
+
+				input
+					.char_indices()
+					.map(|(i, _)| i)
+					.chain(Some(input.len()))
+					.find(|&start| {
+						matcher.reset();
+						input[start..].chars().all(|c| matcher.feed(c)) && matcher.is_match()
+					})
+					.map(|start| start..input.len())
+			}
+			MatchMode::Anywhere => self.find(input),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{nfa::U32StateBuilder, NFA};
+
+	#[test]
+	fn reset() {
+		let nfa: NFA = NFA::from_chars(['a', 'b'], U32StateBuilder::default()).unwrap();
+		let mut matcher = Matcher::new(&nfa);
+
+		assert!(matcher.feed('a'));
+		assert!(!matcher.is_match());
+		assert!(!matcher.feed('a'));
+		assert!(!matcher.feed('b'));
+		assert_eq!(matcher.matched_len(), 3);
+
+		matcher.reset();
+		assert_eq!(matcher.matched_len(), 0);
+		assert!(matcher.feed('a'));
+		assert!(matcher.feed('b'));
+		assert!(matcher.is_match());
+		assert_eq!(matcher.matched_len(), 2);
+	}
+
+	#[test]
+	fn feed_str() {
+		let nfa: NFA = NFA::from_chars(['a', 'b', 'c'], U32StateBuilder::default()).unwrap();
+
+		let mut a = Matcher::new(&nfa);
+		let mut b = Matcher::new(&nfa);
+		assert_eq!(a.feed_str("ab"), b.feed('a') && b.feed('b'));
+		assert_eq!(a.matched_len(), b.matched_len());
+		assert_eq!(a.is_match(), b.is_match());
+
+		assert!(a.feed_str("c"));
+		assert!(a.is_match());
+		assert!(!a.feed_str("de"));
+		assert_eq!(a.matched_len(), 5);
+	}
+
+	#[test]
+	fn byte_matcher() {
+		let nfa: NFA = NFA::from_chars("aé".chars(), U32StateBuilder::default()).unwrap();
+		let dfa = nfa.determinize(|q| q.iter().copied().copied().collect::<Vec<_>>());
+
+		let bytes = "aé".as_bytes();
+		assert_eq!(bytes.len(), 3);
+
+		let mut matcher = ByteMatcher::new(&dfa);
+		assert!(matcher.feed(&bytes[..2]));
+		assert!(!matcher.is_match());
+		assert!(matcher.feed(&bytes[2..]));
+		assert!(matcher.is_match());
+
+		matcher.reset();
+		assert!(matcher.feed(b"a"));
+		assert!(!matcher.feed(&[0xc3, 0x28]));
+		assert!(!matcher.is_match());
+
+		matcher.reset();
+		assert!(!matcher.feed(&[0xff]));
+	}
+
+	#[test]
+	fn dyn_matcher() {
+		let mut state_builder = U32StateBuilder::default();
+		let nfa: NFA = NFA::from_chars("ab".chars(), &mut state_builder).unwrap();
+		let dfa = NFA::from_chars("cd".chars(), &mut state_builder)
+			.unwrap()
+			.determinize(|q| q.iter().copied().copied().collect::<Vec<_>>());
+
+		let matchers: Vec<Box<dyn DynMatcher>> = vec![Box::new(nfa), Box::new(dfa)];
+
+		assert!(matchers[0].is_match("ab"));
+		assert!(!matchers[0].is_match("abc"));
+		assert!(matchers[1].is_match("cd"));
+		assert!(!matchers[1].is_match("ab"));
+
+		assert_eq!(matchers[0].find("xxaby"), Some(2..4));
+		assert_eq!(matchers[1].find("xxaby"), None);
+		assert_eq!(matchers[1].find("dcdc"), Some(1..3));
+	}
+
+	#[test]
+	fn find_with_mode() {
+		let nfa: NFA = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		let matcher: &dyn DynMatcher = &nfa;
+
+		assert_eq!(matcher.find_with_mode("abcd", MatchMode::Full), None);
+		assert_eq!(matcher.find_with_mode("abc", MatchMode::Full), Some(0..3));
+		assert_eq!(
+			matcher.find_with_mode("abcd", MatchMode::Prefix),
+			Some(0..3)
+		);
+		assert_eq!(matcher.find_with_mode("xabcd", MatchMode::Prefix), None);
+		assert_eq!(matcher.find_with_mode("abcd", MatchMode::Suffix), None);
+		assert_eq!(
+			matcher.find_with_mode("xabc", MatchMode::Suffix),
+			Some(1..4)
+		);
+		assert_eq!(
+			matcher.find_with_mode("abcd", MatchMode::Anywhere),
+			Some(0..3)
+		);
+		assert_eq!(
+			matcher.find_with_mode("xabcd", MatchMode::Anywhere),
+			Some(1..4)
+		);
+	}
+}
@@ -1,23 +1,65 @@
 use btree_range_map::{AnyRange, RangeMap, RangeSet};
 use std::{
-	collections::{BTreeMap, BTreeSet, HashSet},
-	hash::Hash,
+	collections::{hash_map::RandomState, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+	hash::{BuildHasher, Hash},
+	ops::ControlFlow,
 };
 
-use crate::{dfa::DetTransitions, Automaton, DFA};
+use crate::{
+	dfa::DetTransitions,
+	threads::{self, Thread},
+	Automaton, DfaTable, Matcher, DFA,
+};
+
+use super::{any_char, charset_intersection};
+
+/// Deterministic state given to a subset of NFA states during
+/// determinization.
+enum Resolved<R> {
+	/// The subset is explored, with the given deterministic state.
+	Explored(R),
+
+	/// The subset is not explored, and replaced by the given final state
+	/// accepting any continuation.
+	Sink(R),
+}
 
-use super::charset_intersection;
+/// Resolves every subset of NFA states as an explored state given by `f`.
+fn explored<'a, Q, R>(
+	mut f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+) -> impl FnMut(&BTreeSet<&'a Q>) -> Resolved<R> {
+	move |det_q| Resolved::Explored(f(det_q))
+}
 
 #[derive(Debug)]
 pub struct TooManyStates;
 
+/// Error returned when matching exceeds its step budget.
+///
+/// See [`NFA::accepts_within`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("matching step budget exceeded")]
+pub struct BudgetExceeded;
+
+/// Structural error in an [`NFA`], returned by [`NFA::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NfaError<Q> {
+	/// A state is referenced, but not declared in the transitions.
+	#[error("undeclared state `{0:?}`")]
+	UndeclaredState(Q),
+
+	/// A state is part of a cycle of epsilon-transitions.
+	#[error("epsilon-transition cycle through state `{0:?}`")]
+	EpsilonCycle(Q),
+}
+
 pub trait StateBuilder<Q> {
 	type Error;
 
 	fn next_state(&mut self, nfa: &mut NFA<Q>) -> Result<Q, Self::Error>;
 }
 
-impl<'a, Q, S: StateBuilder<Q>> StateBuilder<Q> for &'a mut S {
+impl<Q, S: StateBuilder<Q>> StateBuilder<Q> for &mut S {
 	type Error = S::Error;
 
 	fn next_state(&mut self, nfa: &mut NFA<Q>) -> Result<Q, Self::Error> {
@@ -73,10 +115,48 @@ pub trait BuildNFA<Q: Ord> {
 /// Nondeterministic state transitions.
 pub type Transitions<Q> = BTreeMap<Option<RangeSet<char>>, BTreeSet<Q>>;
 
+/// Reverse index of the transitions of an automaton.
+///
+/// See [`NFA::predecessors`].
+pub type Predecessors<'a, Q> = BTreeMap<&'a Q, Vec<(&'a Option<RangeSet<char>>, &'a Q)>>;
+
+/// Character class quantifier.
+///
+/// See [`NFA::from_class_sequence`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Quantifier {
+	/// Exactly one occurrence.
+	One,
+
+	/// Zero or one occurrence (`?`).
+	Optional,
+
+	/// Zero or more occurrences (`*`).
+	Star,
+
+	/// One or more occurrences (`+`).
+	Plus,
+}
+
+/// Key identifying duplicate states in [`NFA::merge_duplicate_states`]:
+/// finality, transitions and tagged transitions.
+type MergeKey<'a, Q> = (bool, &'a Transitions<Q>, &'a [(Q, Tag)]);
+
+/// Tag attached to an epsilon-transition.
+///
+/// Tags are used to track submatches (such as where a capture group starts
+/// or ends) along an accepting path.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tag(pub u32);
+
 /// Nondeterministic finite automaton.
-#[derive(Debug)]
+///
+/// Equality and hashing are structural: two automata recognizing the same
+/// language with different states or transitions are not equal.
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub struct NFA<Q = u32> {
 	transitions: BTreeMap<Q, Transitions<Q>>,
+	tagged_transitions: BTreeMap<Q, Vec<(Q, Tag)>>,
 	initial_states: BTreeSet<Q>,
 	final_states: BTreeSet<Q>,
 }
@@ -85,6 +165,7 @@ impl<Q> Default for NFA<Q> {
 	fn default() -> Self {
 		Self {
 			transitions: BTreeMap::new(),
+			tagged_transitions: BTreeMap::new(),
 			initial_states: BTreeSet::new(),
 			final_states: BTreeSet::new(),
 		}
@@ -98,447 +179,3486 @@ impl<Q> NFA<Q> {
 	}
 
 	/// Returns an iterator over the transitions.
-	pub fn transitions(&self) -> std::collections::btree_map::Iter<Q, Transitions<Q>> {
+	pub fn transitions(&self) -> std::collections::btree_map::Iter<'_, Q, Transitions<Q>> {
 		self.transitions.iter()
 	}
-}
 
-impl<Q: Ord> NFA<Q> {
-	/// Get the successors of the given state.
-	pub fn successors(&self, q: &Q) -> Successors<Q> {
-		Successors::new(self.transitions.get(q))
+	/// Returns an iterator over all the transitions of the automaton, as
+	/// `(source, label, target)` triples.
+	///
+	/// Epsilon-transitions have a `None` label.
+	pub fn edges(&self) -> impl '_ + Iterator<Item = (&Q, &Option<RangeSet<char>>, &Q)> {
+		self.transitions.iter().flat_map(|(q, q_transitions)| {
+			q_transitions
+				.iter()
+				.flat_map(move |(label, targets)| targets.iter().map(move |r| (q, label, r)))
+		})
 	}
 
-	/// Adds the given transition to the automaton.
-	pub fn add(&mut self, source: Q, label: Option<RangeSet<char>>, target: Q)
-	where
-		Q: Clone,
-	{
-		self.add_state(target.clone());
+	/// Returns the number of transitions of the automaton.
+	pub fn num_transitions(&self) -> usize {
 		self.transitions
-			.entry(source)
-			.or_default()
-			.entry(label)
-			.or_default()
-			.insert(target);
-	}
-
-	/// Adds the given state into the automaton, even if it is not the source
-	/// or destination of any transition.
-	pub fn add_state(&mut self, q: Q) {
-		self.transitions.entry(q).or_default();
-	}
-
-	/// Checks if the given state is an initial state.
-	pub fn is_initial_state(&self, q: &Q) -> bool {
-		self.initial_states.contains(q)
+			.values()
+			.flat_map(BTreeMap::values)
+			.map(BTreeSet::len)
+			.sum()
 	}
 
-	/// Sets the given state as an initial state.
-	pub fn add_initial_state(&mut self, q: Q) -> bool {
-		self.initial_states.insert(q)
-	}
+	/// Converts this automaton into a [`petgraph::Graph`].
+	///
+	/// Epsilon-transitions are labeled with `None`.
+	#[cfg(feature = "petgraph")]
+	pub fn to_petgraph(&self) -> petgraph::Graph<Q, Option<RangeSet<char>>>
+	where
+		Q: Clone + Ord,
+	{
+		let mut graph = petgraph::Graph::new();
 
-	/// Checks if the given state is a final state.
-	pub fn is_final_state(&self, q: &Q) -> bool {
-		self.final_states.contains(q)
-	}
+		let mut nodes = BTreeMap::new();
+		for q in self.transitions.keys() {
+			nodes.insert(q, graph.add_node(q.clone()));
+		}
 
-	/// Returns the set of final states.
-	pub fn final_states(&self) -> &BTreeSet<Q> {
-		&self.final_states
-	}
+		for (q, label, r) in self.edges() {
+			let r = *nodes.entry(r).or_insert_with(|| graph.add_node(r.clone()));
+			graph.add_edge(nodes[q], r, label.clone());
+		}
 
-	/// Adds a final state to the automaton.
-	pub fn add_final_state(&mut self, q: Q) -> bool {
-		self.final_states.insert(q)
+		graph
 	}
+}
 
-	/// Checks if this automaton can recognize the empty string.
-	pub fn recognizes_empty(&self) -> bool {
-		let mut stack: Vec<_> = self.initial_states.iter().collect();
-		let mut visited = BTreeSet::new();
+impl<Q: Ord> NFA<Q> {
+	/// Builds an automaton recognizing exactly the string formed by the given
+	/// characters.
+	pub fn from_chars<I, S>(chars: I, mut state_builder: S) -> Result<Self, S::Error>
+	where
+		Q: Clone,
+		I: IntoIterator<Item = char>,
+		S: StateBuilder<Q>,
+	{
+		let mut result = NFA::new();
 
-		while let Some(q) = stack.pop() {
-			if visited.insert(q) {
-				if self.is_final_state(q) {
-					return true;
-				}
+		let mut q = state_builder.next_state(&mut result)?;
+		result.add_initial_state(q.clone());
 
-				if let Some(transitions) = self.transitions.get(q) {
-					if let Some(successors) = transitions.get(&None) {
-						stack.extend(successors)
-					}
-				}
-			}
+		for c in chars {
+			let r = state_builder.next_state(&mut result)?;
+			result.add(q, Some(RangeSet::from_iter([c])), r.clone());
+			q = r;
 		}
 
-		false
+		result.add_final_state(q);
+		Ok(result)
 	}
 
-	/// Checks if this automaton recognizes exactly one string.
-	pub fn is_singleton(&self) -> bool {
-		if self.initial_states.len() > 1 {
-			return false;
-		}
+	/// Builds an automaton recognizing any string over `alphabet` whose length
+	/// is in `lengths`.
+	///
+	/// The automaton is a single chain of states, one per length up to the
+	/// maximum of `lengths`.
+	pub fn from_length_set<S>(
+		lengths: &BTreeSet<usize>,
+		alphabet: RangeSet<char>,
+		mut state_builder: S,
+	) -> Result<Self, S::Error>
+	where
+		Q: Clone,
+		S: StateBuilder<Q>,
+	{
+		let mut result = NFA::new();
 
-		if let Some(mut q) = self.initial_states.first() {
-			while let Some(q_transitions) = self.transitions.get(q) {
-				if q_transitions.len() > 1 {
-					return false;
-				}
+		let mut q = state_builder.next_state(&mut result)?;
+		result.add_initial_state(q.clone());
 
-				match q_transitions.first_key_value() {
-					Some((label, r)) => {
-						if r.len() > 1 {
-							return false;
-						}
+		let max = lengths.last().copied().unwrap_or_default();
+		for len in 0..=max {
+			if lengths.contains(&len) {
+				result.add_final_state(q.clone());
+			}
 
-						match r.first() {
-							Some(r) => match label {
-								Some(range) if range.len() == 1 => q = r,
-								_ => return false,
-							},
-							None => break,
-						}
-					}
-					None => break,
-				}
+			if len < max {
+				let r = state_builder.next_state(&mut result)?;
+				result.add(q, Some(alphabet.clone()), r.clone());
+				q = r;
 			}
 		}
 
-		true
+		Ok(result)
 	}
 
-	/// Returns the string recognized by this automaton if it is a singleton
-	/// automaton (it recognizes exactly one string).
+	/// Builds an automaton recognizing the given sequence of quantified
+	/// character classes, such as `[A-Z][a-z]+[0-9]`.
 	///
-	/// Returns `None` if this automaton recognizes no string, or more than one
-	/// string.
-	pub fn to_singleton(&self) -> Option<String> {
-		if self.initial_states.len() > 1 {
-			return None;
-		}
+	/// The resulting automaton has no epsilon-transition.
+	pub fn from_class_sequence<S>(
+		classes: &[(RangeSet<char>, Quantifier)],
+		mut state_builder: S,
+	) -> Result<Self, S::Error>
+	where
+		Q: Clone,
+		S: StateBuilder<Q>,
+	{
+		let mut result = NFA::new();
 
-		let mut result = String::new();
+		let q = state_builder.next_state(&mut result)?;
+		result.add_initial_state(q.clone());
 
-		if let Some(mut q) = self.initial_states.first() {
-			while let Some(q_transitions) = self.transitions.get(q) {
-				if q_transitions.len() > 1 {
-					return None;
-				}
+		// states from which the next class can be read.
+		let mut ends = vec![q];
 
-				match q_transitions.first_key_value() {
-					Some((label, r)) => {
-						if r.len() > 1 {
-							return None;
-						}
+		for (class, quantifier) in classes {
+			let r = state_builder.next_state(&mut result)?;
 
-						match r.first() {
-							Some(r) => match label {
-								Some(range) if range.len() == 1 => {
-									let c = range.iter().next().unwrap().first().unwrap();
-									result.push(c);
-									q = r
-								}
-								_ => return None,
-							},
-							None => break,
-						}
-					}
-					None => break,
-				}
+			for q in &ends {
+				result.add(q.clone(), Some(class.clone()), r.clone());
 			}
-		}
-
-		Some(result)
-	}
 
-	fn modulo_epsilon_state<'a>(&'a self, qs: impl IntoIterator<Item = &'a Q>) -> BTreeSet<&'a Q> {
-		let mut states = BTreeSet::new();
-		let mut stack: Vec<_> = qs.into_iter().collect();
+			if matches!(quantifier, Quantifier::Star | Quantifier::Plus) {
+				result.add(r.clone(), Some(class.clone()), r.clone());
+			}
 
-		while let Some(q) = stack.pop() {
-			if states.insert(q) {
-				// add states reachable trough epsilon-transitions.
-				if let Some(transitions) = self.transitions.get(q) {
-					if let Some(epsilon_qs) = transitions.get(&None) {
-						for t in epsilon_qs {
-							stack.push(t)
-						}
-					}
-				}
+			if !matches!(quantifier, Quantifier::Optional | Quantifier::Star) {
+				ends.clear();
 			}
+
+			ends.push(r);
 		}
 
-		states
+		result.final_states.extend(ends);
+		Ok(result)
 	}
 
-	fn determinize_transitions_for(
-		&self,
-		states: &BTreeSet<&Q>,
-	) -> BTreeMap<AnyRange<char>, BTreeSet<&Q>> {
-		let mut map = RangeMap::new();
-
-		for q in states {
-			if let Some(transitions) = self.transitions.get(q) {
-				for (label, targets) in transitions {
-					if let Some(label) = label {
-						for range in label.iter() {
-							debug_assert!(!range.is_empty());
+	/// Builds the concatenation of the given automata.
+	///
+	/// The concatenation of no automaton recognizes only the empty string.
+	/// The input automata states must be disjoint, which is the case if they
+	/// were all built using `state_builder`.
+	pub fn concat_all<I, S>(parts: I, mut state_builder: S) -> Result<Self, S::Error>
+	where
+		Q: Clone,
+		I: IntoIterator<Item = Self>,
+		S: StateBuilder<Q>,
+	{
+		let mut result = NFA::new();
 
-							map.update(
-								*range,
-								|current_target_states_opt: Option<&BTreeSet<&Q>>| {
-									let mut current_target_states = match current_target_states_opt
-									{
-										Some(current_target_states) => {
-											current_target_states.clone()
-										}
-										None => BTreeSet::new(),
-									};
+		let q = state_builder.next_state(&mut result)?;
+		result.add_initial_state(q.clone());
 
-									for q in targets {
-										current_target_states
-											.extend(self.modulo_epsilon_state(Some(q)));
-									}
+		let mut ends = BTreeSet::new();
+		ends.insert(q);
 
-									Some(current_target_states)
-								},
-							);
+		for part in parts {
+			let (initial_states, final_states) = result.absorb(part);
 
-							assert!(map.get(range.first().unwrap()).is_some());
-						}
-					}
+			for a in &ends {
+				for b in &initial_states {
+					result.add(a.clone(), None, b.clone());
 				}
 			}
-		}
-
-		let mut simplified_map = BTreeMap::new();
 
-		for (range, set) in map {
-			debug_assert!(!range.is_empty());
-			simplified_map.insert(range, set);
+			ends = final_states;
 		}
 
-		simplified_map
+		result.final_states = ends;
+		Ok(result)
 	}
 
-	/// Turns this NFA into a DFA.
-	pub fn determinize<'a, R>(&'a self, mut f: impl FnMut(&BTreeSet<&'a Q>) -> R) -> DFA<R>
+	/// Builds the union of the given automata.
+	///
+	/// The union of no automaton recognizes the empty language.
+	/// The input automata states must be disjoint, which is the case if they
+	/// were all built using `state_builder`.
+	pub fn union_all<I, S>(alts: I, mut state_builder: S) -> Result<Self, S::Error>
 	where
-		R: Clone + Ord + Hash,
+		Q: Clone,
+		I: IntoIterator<Item = Self>,
+		S: StateBuilder<Q>,
 	{
-		let mut transitions = BTreeMap::new();
-
-		// create the initial deterministic state.
-		let initial_state = self.modulo_epsilon_state(&self.initial_states);
-		let mut final_states = BTreeSet::new();
-
-		let mut visited_states = HashSet::new();
-		let mut stack = vec![initial_state.clone()];
-		while let Some(det_q) = stack.pop() {
-			let r = f(&det_q);
-			if visited_states.insert(r.clone()) {
-				if det_q.iter().any(|q| self.final_states.contains(q)) {
-					final_states.insert(r.clone());
-				}
+		let mut result = NFA::new();
 
-				let map = self.determinize_transitions_for(&det_q);
+		let q = state_builder.next_state(&mut result)?;
+		result.add_initial_state(q.clone());
 
-				let mut r_map = BTreeMap::new();
-				for (label, next_det_q) in map {
-					r_map.insert(label, f(&next_det_q));
-					stack.push(next_det_q)
-				}
+		for alt in alts {
+			let (initial_states, final_states) = result.absorb(alt);
 
-				transitions.insert(r, r_map);
+			for r in initial_states {
+				result.add(q.clone(), None, r);
 			}
+
+			result.final_states.extend(final_states);
 		}
 
-		DFA::from_parts(
-			f(&initial_state),
-			final_states,
-			DetTransitions::from(transitions),
-		)
+		Ok(result)
 	}
 
-	/// Adds the given `other` automaton to `self`, mapping the other automaton
-	/// states in the process.
-	pub fn mapped_union<R>(&mut self, other: NFA<R>, f: impl Fn(R) -> Q) {
-		for (q, transitions) in other.transitions {
-			let this_transitions = self.transitions.entry(f(q)).or_default();
-			for (label, targets) in transitions {
-				this_transitions
-					.entry(label)
-					.or_default()
-					.extend(targets.into_iter().map(&f));
+	/// Builds the ordered union of the given automata.
+	///
+	/// This is the same as [`Self::union_all`], except that the branches are
+	/// entered through tagged epsilon-transitions, the `i`-th branch being
+	/// tagged with `Tag(i)`, in order. This order is used by
+	/// [`Self::match_first`] to prefer the first branch that matches, as in
+	/// the alternations of PCRE-like engines.
+	pub fn with_priorities<I, S>(alts: I, mut state_builder: S) -> Result<Self, S::Error>
+	where
+		Q: Clone,
+		I: IntoIterator<Item = Self>,
+		S: StateBuilder<Q>,
+	{
+		let mut result = NFA::new();
+
+		let q = state_builder.next_state(&mut result)?;
+		result.add_initial_state(q.clone());
+
+		for (i, alt) in (0..).zip(alts) {
+			let (initial_states, final_states) = result.absorb(alt);
+
+			for r in initial_states {
+				result.add_tagged_epsilon(q.clone(), r, Tag(i));
 			}
+
+			result.final_states.extend(final_states);
 		}
 
-		self.initial_states
-			.extend(other.initial_states.into_iter().map(&f));
-		self.final_states
-			.extend(other.final_states.into_iter().map(f));
+		Ok(result)
 	}
 
-	/// Adds the given `other` automaton to `self`.
-	pub fn union<R>(&mut self, other: NFA<Q>) {
-		self.mapped_union(other, |q| q)
+	/// Adds a fresh state with an epsilon-transition to every initial state,
+	/// and makes it the only initial state.
+	///
+	/// The recognized language is unchanged. Returns the new initial state.
+	pub fn prepend_epsilon_start<S>(&mut self, mut state_builder: S) -> Result<Q, S::Error>
+	where
+		Q: Clone,
+		S: StateBuilder<Q>,
+	{
+		let q = state_builder.next_state(self)?;
+
+		for r in std::mem::take(&mut self.initial_states) {
+			self.add(q.clone(), None, r)
+		}
+
+		self.add_initial_state(q.clone());
+		Ok(q)
 	}
 
-	/// Computes the product between `self` and `other`.
+	/// Adds a fresh state reached by an epsilon-transition from every final
+	/// state, and makes it the only final state.
 	///
-	/// The input function `f` computes the product between two states.
-	pub fn product<'a, 'b, R, S>(
-		&'a self,
-		other: &'b NFA<R>,
-		mut f: impl FnMut(&'a Q, &'b R) -> S,
-	) -> NFA<S>
+	/// The recognized language is unchanged. Returns the new final state.
+	pub fn append_epsilon_accept<S>(&mut self, mut state_builder: S) -> Result<Q, S::Error>
 	where
-		R: Ord,
-		S: Clone + Ord + Hash,
+		Q: Clone,
+		S: StateBuilder<Q>,
 	{
-		let mut result = NFA::new();
+		let q = state_builder.next_state(self)?;
 
-		let mut stack = Vec::with_capacity(self.initial_states.len() * other.initial_states.len());
-		for a in &self.initial_states {
-			for b in &other.initial_states {
-				let q = f(a, b);
-				stack.push((q.clone(), a, b));
-				result.add_initial_state(q);
-			}
+		for r in std::mem::take(&mut self.final_states) {
+			self.add(r, None, q.clone())
 		}
 
-		let mut visited = HashSet::new();
-		while let Some((q, a, b)) = stack.pop() {
-			if visited.insert(q.clone()) {
-				if self.is_final_state(a) && other.is_final_state(b) {
-					result.add_final_state(q.clone());
-				}
+		self.add_final_state(q.clone());
+		Ok(q)
+	}
 
-				let transitions = result.transitions.entry(q).or_default();
+	/// Splices `sub` between the states `at.0` and `at.1`.
+	///
+	/// Epsilon-transitions are added from `at.0` to every initial state of
+	/// `sub`, and from every final state of `sub` to `at.1`. The initial and
+	/// final states of `sub` are not initial or final in `self`. The states of
+	/// `sub` must be disjoint from the states of `self`, which is the case if
+	/// both were built using the same [`StateBuilder`].
+	///
+	/// This can be used to fill a placeholder in a template automaton: if
+	/// `at.0` is reached after reading `u` and `at.1` leads to a final state
+	/// by reading `v`, then `self` now recognizes `uwv` for every `w`
+	/// recognized by `sub`.
+	pub fn substitute(&mut self, at: (Q, Q), sub: NFA<Q>)
+	where
+		Q: Clone,
+	{
+		let (start, end) = at;
+		let (initial_states, final_states) = self.absorb(sub);
+
+		for q in initial_states {
+			self.add(start.clone(), None, q)
+		}
+
+		for q in final_states {
+			self.add(q, None, end.clone())
+		}
+	}
+
+	/// Merges the states having exactly the same outgoing transitions
+	/// (including tagged transitions) and finality, redirecting their
+	/// incoming transitions.
+	///
+	/// This is a cheap heuristic to reduce the size of the automaton before
+	/// determinization, without changing the recognized language. It is not
+	/// a minimization: equivalent states with different transitions are not
+	/// merged. Merging is repeated until no duplicate is left, so that
+	/// structurally identical sub-automata (for instance in a union of
+	/// literals) are entirely merged.
+	#[doc(alias = "dedup_equivalent_states")]
+	pub fn merge_duplicate_states(&mut self)
+	where
+		Q: Clone,
+	{
+		loop {
+			let mut representatives: BTreeMap<MergeKey<'_, Q>, &Q> = BTreeMap::new();
+			let mut replacements = BTreeMap::new();
+			for (q, q_transitions) in &self.transitions {
+				let q_tagged_transitions = self
+					.tagged_transitions
+					.get(q)
+					.map_or(&[][..], Vec::as_slice);
+				let key = (
+					self.final_states.contains(q),
+					q_transitions,
+					q_tagged_transitions,
+				);
+				match representatives.get(&key) {
+					Some(r) => {
+						replacements.insert(q.clone(), (*r).clone());
+					}
+					None => {
+						representatives.insert(key, q);
+					}
+				}
+			}
+
+			if replacements.is_empty() {
+				break;
+			}
+
+			let replace = |q: Q| replacements.get(&q).cloned().unwrap_or(q);
+
+			for q in replacements.keys() {
+				self.transitions.remove(q);
+				self.final_states.remove(q);
+			}
+
+			for q_transitions in self.transitions.values_mut() {
+				for targets in q_transitions.values_mut() {
+					*targets = std::mem::take(targets).into_iter().map(replace).collect()
+				}
+			}
+
+			let tagged_transitions = std::mem::take(&mut self.tagged_transitions);
+			for (q, q_tagged_transitions) in tagged_transitions {
+				let r_tagged_transitions = self.tagged_transitions.entry(replace(q)).or_default();
+				for (r, tag) in q_tagged_transitions {
+					let t = (replace(r), tag);
+					if !r_tagged_transitions.contains(&t) {
+						r_tagged_transitions.push(t)
+					}
+				}
+			}
+
+			self.initial_states = std::mem::take(&mut self.initial_states)
+				.into_iter()
+				.map(replace)
+				.collect();
+		}
+	}
+
+	/// Removes the intermediate states whose outgoing transitions are all
+	/// epsilon-transitions, redirecting their incoming transitions to their
+	/// successors.
+	///
+	/// Initial and final states, and states with tagged transitions, are
+	/// kept. This does not change the recognized language, and is cheaper
+	/// than removing every epsilon-transition: other epsilon-transitions are
+	/// kept as is.
+	pub fn collapse_epsilon_chains(&mut self)
+	where
+		Q: Clone,
+	{
+		let tagged: BTreeSet<Q> = self
+			.tagged_transitions
+			.iter()
+			.flat_map(|(q, q_tagged)| Some(q).into_iter().chain(q_tagged.iter().map(|(r, _)| r)))
+			.cloned()
+			.collect();
+
+		let candidates: Vec<Q> = self
+			.transitions
+			.iter()
+			.filter(|(q, q_transitions)| {
+				!q_transitions.is_empty()
+					&& q_transitions.keys().all(Option::is_none)
+					&& !self.initial_states.contains(q)
+					&& !self.final_states.contains(q)
+					&& !tagged.contains(q)
+			})
+			.map(|(q, _)| q.clone())
+			.collect();
+
+		// Sources of the incoming transitions of each state, kept up to date
+		// as chains are collapsed so that only the edges entering a removed
+		// state are visited.
+		let mut predecessors: BTreeMap<Q, BTreeSet<Q>> = self
+			.predecessors()
+			.into_iter()
+			.map(|(r, sources)| {
+				(
+					r.clone(),
+					sources.into_iter().map(|(_, p)| p.clone()).collect(),
+				)
+			})
+			.collect();
+
+		for q in candidates {
+			let mut successors = self
+				.transitions
+				.remove(&q)
+				.and_then(|mut q_transitions| q_transitions.remove(&None))
+				.unwrap_or_default();
+			successors.remove(&q);
+
+			for r in &successors {
+				if let Some(r_predecessors) = predecessors.get_mut(r) {
+					r_predecessors.remove(&q);
+				}
+			}
+
+			for p in predecessors.remove(&q).unwrap_or_default() {
+				let Some(p_transitions) = self.transitions.get_mut(&p) else {
+					continue;
+				};
+
+				for (label, targets) in p_transitions.iter_mut() {
+					if targets.remove(&q) {
+						for r in &successors {
+							if label.is_some() || *r != p {
+								targets.insert(r.clone());
+								predecessors.entry(r.clone()).or_default().insert(p.clone());
+							}
+						}
+					}
+				}
+
+				p_transitions.retain(|_, targets| !targets.is_empty())
+			}
+		}
+	}
+
+	/// Adds the states and transitions of `other` to `self`, returning the
+	/// initial and final states of `other`.
+	fn absorb(&mut self, other: Self) -> (BTreeSet<Q>, BTreeSet<Q>) {
+		for (q, transitions) in other.transitions {
+			let this_transitions = self.transitions.entry(q).or_default();
+			for (label, targets) in transitions {
+				this_transitions.entry(label).or_default().extend(targets);
+			}
+		}
+
+		for (q, tagged_transitions) in other.tagged_transitions {
+			self.tagged_transitions
+				.entry(q)
+				.or_default()
+				.extend(tagged_transitions);
+		}
+
+		(other.initial_states, other.final_states)
+	}
+
+	/// Get the successors of the given state.
+	pub fn successors(&self, q: &Q) -> Successors<'_, Q> {
+		Successors::new(self.transitions.get(q))
+	}
+
+	/// Returns, for each target of the transitions leaving `q`, the set of
+	/// characters leading to it.
+	///
+	/// The labels of the transitions to the same target are merged.
+	/// Epsilon-transitions are ignored.
+	pub fn successor_labels(&self, q: &Q) -> BTreeMap<&Q, RangeSet<char>> {
+		let mut result: BTreeMap<_, RangeSet<char>> = BTreeMap::new();
+
+		for (label, targets) in self.successors(q) {
+			if let Some(label) = label {
+				for r in targets {
+					let r_label = result.entry(r).or_default();
+					for range in label.iter() {
+						r_label.insert(*range)
+					}
+				}
+			}
+		}
+
+		result
+	}
+
+	/// Builds the reverse index of the transitions: for each state, the
+	/// `(label, source)` pairs of its incoming transitions.
+	///
+	/// States without incoming transitions are absent from the index.
+	/// Epsilon-transitions have a `None` label. The index is recomputed on
+	/// each call, callers performing several backward analyses should cache
+	/// it.
+	pub fn predecessors(&self) -> Predecessors<'_, Q> {
+		let mut result: BTreeMap<_, Vec<_>> = BTreeMap::new();
+
+		for (q, label, r) in self.edges() {
+			result.entry(r).or_default().push((label, q))
+		}
+
+		result
+	}
+
+	/// Adds the given transition to the automaton.
+	pub fn add(&mut self, source: Q, label: Option<RangeSet<char>>, target: Q)
+	where
+		Q: Clone,
+	{
+		self.add_state(target.clone());
+		self.transitions
+			.entry(source)
+			.or_default()
+			.entry(label)
+			.or_default()
+			.insert(target);
+	}
+
+	/// Adds the given transitions, all leaving `source`, to the automaton.
+	///
+	/// This is equivalent to calling [`Self::add`] for each edge, but the
+	/// edges are first grouped by label and target, so that the transitions
+	/// of `source` are looked up only once, and each distinct target once.
+	/// This is useful when building states with many successors.
+	pub fn add_many(
+		&mut self,
+		source: Q,
+		edges: impl IntoIterator<Item = (Option<RangeSet<char>>, Q)>,
+	) where
+		Q: Clone,
+	{
+		let mut edges_transitions: Transitions<Q> = BTreeMap::new();
+		for (label, target) in edges {
+			edges_transitions.entry(label).or_default().insert(target);
+		}
+
+		let targets: BTreeSet<&Q> = edges_transitions.values().flatten().collect();
+		for target in targets {
+			if *target != source {
+				self.add_state(target.clone())
+			}
+		}
+
+		let source_transitions = self.transitions.entry(source).or_default();
+		if source_transitions.is_empty() {
+			*source_transitions = edges_transitions
+		} else {
+			for (label, mut label_targets) in edges_transitions {
+				source_transitions
+					.entry(label)
+					.or_default()
+					.append(&mut label_targets)
+			}
+		}
+	}
+
+	/// Adds a transition from `source` for each target of the given map,
+	/// labeled by the characters mapped to this target.
+	///
+	/// Ranges mapped to the same target are grouped into a single label.
+	pub fn add_range_map(&mut self, source: Q, map: RangeMap<char, Q>)
+	where
+		Q: Clone,
+	{
+		let mut labels: BTreeMap<Q, RangeSet<char>> = BTreeMap::new();
+		for (range, target) in map {
+			labels.entry(target).or_default().insert(range)
+		}
+
+		self.add_many(
+			source,
+			labels
+				.into_iter()
+				.map(|(target, label)| (Some(label), target)),
+		)
+	}
+
+	/// Adds a tagged epsilon-transition to the automaton.
+	///
+	/// The transition is also added as a regular epsilon-transition so that
+	/// the recognized language is unaffected by tags. Tags are only reported
+	/// by [`Self::match_with_tags`].
+	pub fn add_tagged_epsilon(&mut self, source: Q, target: Q, tag: Tag)
+	where
+		Q: Clone,
+	{
+		self.add(source.clone(), None, target.clone());
+		self.tagged_transitions
+			.entry(source)
+			.or_default()
+			.push((target, tag));
+	}
+
+	/// Adds the given state into the automaton, even if it is not the source
+	/// or destination of any transition.
+	pub fn add_state(&mut self, q: Q) {
+		self.transitions.entry(q).or_default();
+	}
+
+	/// Checks if the given state is an initial state.
+	pub fn is_initial_state(&self, q: &Q) -> bool {
+		self.initial_states.contains(q)
+	}
+
+	/// Returns the set of initial states.
+	pub fn initial_states(&self) -> &BTreeSet<Q> {
+		&self.initial_states
+	}
+
+	/// Sets the given state as an initial state.
+	pub fn add_initial_state(&mut self, q: Q) -> bool {
+		self.initial_states.insert(q)
+	}
+
+	/// Checks if the given state is a final state.
+	pub fn is_final_state(&self, q: &Q) -> bool {
+		self.final_states.contains(q)
+	}
+
+	/// Returns the set of final states.
+	pub fn final_states(&self) -> &BTreeSet<Q> {
+		&self.final_states
+	}
+
+	/// Adds a final state to the automaton.
+	pub fn add_final_state(&mut self, q: Q) -> bool {
+		self.final_states.insert(q)
+	}
+
+	/// Checks that every state referenced by a transition, or as an initial
+	/// or final state, is declared.
+	///
+	/// A state is declared when it is added with [`Self::add_state`] or as
+	/// the source or target of a transition with [`Self::add`]. Initial and
+	/// final states are not automatically declared, so setting an initial or
+	/// final state that is not otherwise part of the automaton is reported as
+	/// an error. See [`Self::validate_epsilon_acyclic`] to also check for
+	/// epsilon-transition cycles.
+	pub fn validate(&self) -> Result<(), NfaError<Q>>
+	where
+		Q: Clone,
+	{
+		let referenced = self
+			.initial_states
+			.iter()
+			.chain(&self.final_states)
+			.chain(self.edges().map(|(_, _, r)| r))
+			.chain(self.tagged_transitions.iter().flat_map(|(q, q_tagged)| {
+				Some(q).into_iter().chain(q_tagged.iter().map(|(r, _)| r))
+			}));
+
+		for q in referenced {
+			if !self.transitions.contains_key(q) {
+				return Err(NfaError::UndeclaredState(q.clone()));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Checks the automaton like [`Self::validate`], and also checks that
+	/// there is no cycle of epsilon-transitions.
+	///
+	/// Such cycles do not change the recognized language, but are usually the
+	/// sign of a construction error.
+	pub fn validate_epsilon_acyclic(&self) -> Result<(), NfaError<Q>>
+	where
+		Q: Clone,
+	{
+		self.validate()?;
+
+		// depth-first search, where `on_path` holds the states being visited.
+		let mut visited = BTreeSet::new();
+		for root in self.transitions.keys() {
+			if visited.contains(root) {
+				continue;
+			}
+
+			let mut on_path = BTreeSet::new();
+			let mut stack = vec![(root, false)];
+			while let Some((q, done)) = stack.pop() {
+				if done {
+					on_path.remove(q);
+					continue;
+				}
+
+				if on_path.contains(q) {
+					return Err(NfaError::EpsilonCycle(q.clone()));
+				}
+
+				if visited.insert(q) {
+					on_path.insert(q);
+					stack.push((q, true));
+
+					if let Some(targets) = self.transitions.get(q).and_then(|t| t.get(&None)) {
+						stack.extend(targets.iter().map(|r| (r, false)))
+					}
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Checks if this automaton can recognize the empty string.
+	pub fn recognizes_empty(&self) -> bool {
+		let mut stack: Vec<_> = self.initial_states.iter().collect();
+		let mut visited = BTreeSet::new();
+
+		while let Some(q) = stack.pop() {
+			if visited.insert(q) {
+				if self.is_final_state(q) {
+					return true;
+				}
+
+				if let Some(transitions) = self.transitions.get(q) {
+					if let Some(successors) = transitions.get(&None) {
+						stack.extend(successors)
+					}
+				}
+			}
+		}
+
+		false
+	}
+
+	/// Checks if the transition graph of this automaton contains a cycle,
+	/// including cycles of epsilon-transitions.
+	pub fn is_cyclic(&self) -> bool {
+		// states fully explored.
+		let mut done = BTreeSet::new();
+
+		for start in self.transitions.keys() {
+			if done.contains(start) {
+				continue;
+			}
+
+			// states on the current path, with their remaining successors.
+			let mut on_path = BTreeSet::new();
+			let mut stack = vec![(start, self.edges_from(start))];
+			on_path.insert(start);
+
+			while let Some((q, successors)) = stack.last_mut() {
+				match successors.next() {
+					Some(r) => {
+						if on_path.contains(r) {
+							return true;
+						}
+
+						if !done.contains(r) {
+							on_path.insert(r);
+							stack.push((r, self.edges_from(r)))
+						}
+					}
+					None => {
+						on_path.remove(*q);
+						done.insert(*q);
+						stack.pop();
+					}
+				}
+			}
+		}
+
+		false
+	}
+
+	/// Computes the strongly connected components of the transition graph of
+	/// this automaton, including epsilon-transitions, using Tarjan's
+	/// algorithm.
+	///
+	/// Every state belongs to exactly one component. Components are returned
+	/// in reverse topological order: no transition leads from a component to
+	/// a component appearing before it. A component with more than one state,
+	/// or whose single state has a transition to itself, corresponds to a
+	/// loop of the automaton.
+	pub fn strongly_connected_components(&self) -> Vec<BTreeSet<&Q>> {
+		let mut result = Vec::new();
+
+		let mut index: BTreeMap<&Q, usize> = BTreeMap::new();
+		let mut low_link: BTreeMap<&Q, usize> = BTreeMap::new();
+		let mut component_stack = Vec::new();
+		let mut on_stack = BTreeSet::new();
+
+		for start in self.transitions.keys() {
+			if index.contains_key(start) {
+				continue;
+			}
+
+			index.insert(start, index.len());
+			low_link.insert(start, index[start]);
+			component_stack.push(start);
+			on_stack.insert(start);
+			let mut stack = vec![(start, self.edges_from(start))];
+
+			while let Some((q, successors)) = stack.last_mut() {
+				let q: &Q = q;
+				match successors.next() {
+					Some(r) => {
+						if !index.contains_key(r) {
+							index.insert(r, index.len());
+							low_link.insert(r, index[r]);
+							component_stack.push(r);
+							on_stack.insert(r);
+							stack.push((r, self.edges_from(r)))
+						} else if on_stack.contains(r) {
+							let low = low_link[q].min(index[r]);
+							low_link.insert(q, low);
+						}
+					}
+					None => {
+						stack.pop();
+
+						if let Some((p, _)) = stack.last() {
+							let low = low_link[*p].min(low_link[q]);
+							low_link.insert(p, low);
+						}
+
+						if low_link[q] == index[q] {
+							let mut component = BTreeSet::new();
+							while let Some(r) = component_stack.pop() {
+								on_stack.remove(r);
+								component.insert(r);
+								if r == q {
+									break;
+								}
+							}
+
+							result.push(component)
+						}
+					}
+				}
+			}
+		}
+
+		result
+	}
+
+	/// Returns an iterator over the targets of the transitions leaving `q`.
+	fn edges_from<'a>(&'a self, q: &Q) -> impl 'a + Iterator<Item = &'a Q> {
+		self.transitions
+			.get(q)
+			.into_iter()
+			.flatten()
+			.flat_map(|(_, targets)| targets)
+	}
+
+	/// Checks if this automaton recognizes exactly one string.
+	///
+	/// Epsilon-transitions are taken into account, and states that do not
+	/// lead to a final state are ignored. Returns `false` if the automaton
+	/// recognizes no string.
+	///
+	/// See [`Self::to_singleton`] to also get the recognized string.
+	pub fn is_singleton(&self) -> bool {
+		self.to_singleton().is_some()
+	}
+
+	/// Returns the string recognized by this automaton if it is a singleton
+	/// automaton (it recognizes exactly one string).
+	///
+	/// Epsilon-transitions are taken into account, and states that do not
+	/// lead to a final state are ignored. Returns `None` if this automaton
+	/// recognizes no string, or more than one string.
+	pub fn to_singleton(&self) -> Option<String> {
+		let productive = self.productive_states();
+
+		let mut current = self.modulo_epsilon_state(
+			self.initial_states
+				.iter()
+				.filter(|q| productive.contains(q)),
+		);
+
+		let mut result = String::new();
+
+		// Since every state in `current` leads to a final state, if no state
+		// is final there is exactly one character to read next, and reading it
+		// brings us closer to a final state.
+		loop {
+			if current.is_empty() {
+				return None;
+			}
+
+			let mut label: Option<char> = None;
+			let mut next = Vec::new();
+			for q in &current {
+				for (q_label, targets) in self.successors(q) {
+					if let Some(q_label) = q_label {
+						for r in targets.iter().filter(|r| productive.contains(r)) {
+							let c = match q_label.iter().next() {
+								Some(range) if q_label.len() == 1 => range.first().unwrap(),
+								_ => return None,
+							};
+
+							if label.map_or(false, |d| c != d) {
+								return None;
+							}
+
+							label = Some(c);
+
+							next.push(r)
+						}
+					}
+				}
+			}
+
+			if current.iter().any(|q| self.is_final_state(q)) {
+				return if next.is_empty() { Some(result) } else { None };
+			}
+
+			result.extend(label);
+			current = self
+				.modulo_epsilon_state(next)
+				.into_iter()
+				.filter(|q| productive.contains(q))
+				.collect();
+		}
+	}
+
+	fn modulo_epsilon_state<'a>(&'a self, qs: impl IntoIterator<Item = &'a Q>) -> BTreeSet<&'a Q> {
+		let mut states = BTreeSet::new();
+		let mut stack: Vec<_> = qs.into_iter().collect();
+
+		while let Some(q) = stack.pop() {
+			if states.insert(q) {
+				// add states reachable trough epsilon-transitions.
+				if let Some(transitions) = self.transitions.get(q) {
+					if let Some(epsilon_qs) = transitions.get(&None) {
+						for t in epsilon_qs {
+							stack.push(t)
+						}
+					}
+				}
+			}
+		}
+
+		states
+	}
+
+	fn determinize_transitions_for(
+		&self,
+		states: &BTreeSet<&Q>,
+	) -> BTreeMap<AnyRange<char>, BTreeSet<&Q>> {
+		let mut map = RangeMap::new();
+
+		for q in states {
+			if let Some(transitions) = self.transitions.get(q) {
+				for (label, targets) in transitions {
+					if let Some(label) = label {
+						for range in label.iter() {
+							debug_assert!(!range.is_empty());
+
+							map.update(
+								*range,
+								|current_target_states_opt: Option<&BTreeSet<&Q>>| {
+									let mut current_target_states = match current_target_states_opt
+									{
+										Some(current_target_states) => {
+											current_target_states.clone()
+										}
+										None => BTreeSet::new(),
+									};
+
+									for q in targets {
+										current_target_states
+											.extend(self.modulo_epsilon_state(Some(q)));
+									}
+
+									Some(current_target_states)
+								},
+							);
+
+							assert!(map.get(range.first().unwrap()).is_some());
+						}
+					}
+				}
+			}
+		}
+
+		let mut simplified_map = BTreeMap::new();
+
+		for (range, set) in map {
+			debug_assert!(!range.is_empty());
+			simplified_map.insert(range, set);
+		}
+
+		simplified_map
+	}
+
+	/// Turns this NFA into a DFA.
+	///
+	/// The function `f` maps each subset of NFA states to a DFA state. It must
+	/// be injective: two distinct subsets must not be mapped to the same DFA
+	/// state, otherwise the resulting automaton is incorrect. This is checked
+	/// in debug builds.
+	pub fn determinize<'a, R>(&'a self, f: impl FnMut(&BTreeSet<&'a Q>) -> R) -> DFA<R>
+	where
+		R: Clone + Ord + Hash,
+	{
+		self.determinize_observed(f, |_| ControlFlow::Continue(()))
+			.unwrap()
+	}
+
+	/// Compiles this NFA directly into a flat deterministic transition table.
+	///
+	/// This is equivalent to determinizing the automaton and converting the
+	/// result with [`DFA::to_table`], but the intermediate [`DFA`] is never
+	/// built. Fails if the table would have more than `max_states` states,
+	/// which bounds the exponential blowup of the subset construction, or
+	/// more than `u32::MAX` states.
+	pub fn compile_table(&self, max_states: usize) -> Result<DfaTable, TooManyStates> {
+		if max_states == 0 {
+			return Err(TooManyStates);
+		}
+
+		let mut table = DfaTable::default();
+
+		let initial_state = self.modulo_epsilon_state(&self.initial_states);
+		let mut ids = BTreeMap::new();
+		ids.insert(initial_state.clone(), 0u32);
+
+		// states are numbered in the order they are visited, so that their
+		// transitions are pushed contiguously.
+		let mut queue = VecDeque::new();
+		queue.push_back(initial_state);
+		while let Some(det_q) = queue.pop_front() {
+			table.push_state(det_q.iter().any(|q| self.final_states.contains(q)));
+
+			for (label, next_det_q) in self.determinize_transitions_for(&det_q) {
+				let len = ids.len();
+				let r = match ids.get(&next_det_q) {
+					Some(r) => *r,
+					None => {
+						if len >= max_states {
+							return Err(TooManyStates);
+						}
+
+						let r = u32::try_from(len).map_err(|_| TooManyStates)?;
+						ids.insert(next_det_q.clone(), r);
+						queue.push_back(next_det_q);
+						r
+					}
+				};
+
+				table.push_transition(label.first().unwrap(), label.last().unwrap(), r);
+			}
+		}
+
+		Ok(table)
+	}
+
+	/// Turns this NFA into a DFA with integer states, also returning the
+	/// subset of NFA states represented by each DFA state.
+	///
+	/// The DFA state `i` represents the subset at index `i` in the returned
+	/// vector.
+	#[doc(alias = "determinize_mapped")]
+	pub fn determinize_with_mapping(&self) -> (DFA<u32>, Vec<BTreeSet<Q>>)
+	where
+		Q: Clone + Hash,
+	{
+		let mut ids = HashMap::new();
+		let mut subsets = Vec::new();
+
+		let dfa = self.determinize(|det_q| {
+			*ids.entry(det_q.clone()).or_insert_with(|| {
+				subsets.push(det_q.iter().copied().cloned().collect());
+				subsets.len() as u32 - 1
+			})
+		});
+
+		(dfa, subsets)
+	}
+
+	/// Turns this NFA into a DFA, also returning the tokens recognized by each
+	/// final state.
+	///
+	/// The `token` function gives the token recognized by each final state of
+	/// `self`, if any. Each final DFA state is then associated to the tokens
+	/// of the final NFA states it represents. This is useful for lexers built
+	/// from the union of multiple token patterns, to know which patterns
+	/// matched. The returned map can be used with [`DFA::run_classify`].
+	///
+	/// See [`Self::determinize`] for the requirements on `f`.
+	pub fn determinize_tagged<'a, R, T>(
+		&'a self,
+		mut f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+		token: impl Fn(&Q) -> Option<T>,
+	) -> (DFA<R>, BTreeMap<R, BTreeSet<T>>)
+	where
+		R: Clone + Ord + Hash,
+		T: Ord,
+	{
+		let mut tokens = BTreeMap::new();
+
+		let dfa = self.determinize(|det_q| {
+			let r = f(det_q);
+
+			if !tokens.contains_key(&r) {
+				let r_tokens: BTreeSet<T> = det_q
+					.iter()
+					.filter(|q| self.is_final_state(q))
+					.filter_map(|q| token(q))
+					.collect();
+
+				if !r_tokens.is_empty() {
+					tokens.insert(r.clone(), r_tokens);
+				}
+			}
+
+			r
+		});
+
+		(dfa, tokens)
+	}
+
+	/// Turns this NFA into a complete DFA.
+	///
+	/// Every character not covered by the transitions of a state leads to the
+	/// given `dead` state, which loops on itself for any character. The `dead`
+	/// state must be distinct from the states produced by `f`.
+	/// Missing transitions are filled in by the subset construction itself,
+	/// without a separate completion pass.
+	///
+	/// See [`Self::determinize`] for the requirements on `f`.
+	pub fn determinize_complete<'a, R>(
+		&'a self,
+		f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+		dead: R,
+	) -> DFA<R>
+	where
+		R: Clone + Ord + Hash,
+	{
+		self.determinize_generic_with_hasher(
+			explored(f),
+			|det_q| det_q.iter().any(|q| self.final_states.contains(q)),
+			|_| ControlFlow::Continue(()),
+			RandomState::new(),
+			Some(dead),
+		)
+		.unwrap()
+	}
+
+	/// Turns this NFA into a DFA with at most `limit` states, plus a fallback
+	/// state, by over-approximating the language if necessary.
+	///
+	/// Once `limit` deterministic states have been created, every new subset
+	/// of states is replaced by the `fallback` state, which is final and
+	/// accepts any continuation. The resulting automaton hence recognizes a
+	/// superset of the language of `self`: it never rejects a string
+	/// recognized by `self`, but may accept strings that `self` rejects. This
+	/// trades precision for a bounded size, which is suitable for
+	/// prefiltering. If `limit` is not reached, the result is exact.
+	///
+	/// See [`Self::determinize`] for the requirements on `f`, which must
+	/// additionally never return the fallback state.
+	pub fn determinize_bounded<'a, R>(
+		&'a self,
+		limit: usize,
+		mut f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+		fallback: impl Fn() -> R,
+	) -> DFA<R>
+	where
+		R: Clone + Ord + Hash,
+	{
+		let fallback = fallback();
+
+		let mut ids: BTreeMap<BTreeSet<&Q>, R> = BTreeMap::new();
+		self.determinize_generic(
+			|det_q| match ids.get(det_q) {
+				Some(r) => Resolved::Explored(r.clone()),
+				None if ids.len() < limit => {
+					let r = f(det_q);
+					ids.insert(det_q.clone(), r.clone());
+					Resolved::Explored(r)
+				}
+				None => Resolved::Sink(fallback.clone()),
+			},
+			|det_q| det_q.iter().any(|q| self.final_states.contains(q)),
+			|_| ControlFlow::Continue(()),
+		)
+		.unwrap()
+	}
+
+	/// Turns this NFA into a DFA, calling `progress` with the number of
+	/// deterministic states created so far each time a new one is created.
+	///
+	/// Use [`Self::determinize_observed`] to also be able to abort the
+	/// determinization.
+	///
+	/// See [`Self::determinize`] for the requirements on `f`.
+	pub fn determinize_with_progress<'a, R>(
+		&'a self,
+		f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+		mut progress: impl FnMut(usize),
+	) -> DFA<R>
+	where
+		R: Clone + Ord + Hash,
+	{
+		self.determinize_observed(f, |n| {
+			progress(n);
+			ControlFlow::Continue(())
+		})
+		.unwrap()
+	}
+
+	/// Counts the states of the DFA obtained by determinizing this automaton,
+	/// without building it.
+	///
+	/// Only the visited subsets of states are stored, not the transitions
+	/// between them. Returns `None` as soon as the count exceeds `limit`, if
+	/// any, which can be used to cheaply detect patterns whose deterministic
+	/// automaton is too large.
+	pub fn determinization_size_estimate(&self, limit: Option<usize>) -> Option<usize> {
+		let initial_state = self.modulo_epsilon_state(&self.initial_states);
+
+		let mut visited = BTreeSet::new();
+		let mut stack = vec![initial_state];
+		while let Some(det_q) = stack.pop() {
+			if !visited.contains(&det_q) {
+				if limit.map_or(false, |limit| visited.len() >= limit) {
+					return None;
+				}
+
+				stack.extend(self.determinize_transitions_for(&det_q).into_values());
+				visited.insert(det_q);
+			}
+		}
+
+		Some(visited.len())
+	}
+
+	/// Turns this NFA into a DFA, reporting progress along the way.
+	///
+	/// The `progress` function is called each time a new deterministic state
+	/// is created, with the number of states created so far. The
+	/// determinization is aborted, returning `None`, as soon as `progress`
+	/// returns [`ControlFlow::Break`]. This can be used to enforce a time or
+	/// memory budget.
+	///
+	/// See [`Self::determinize`] for the requirements on `f`.
+	pub fn determinize_observed<'a, R>(
+		&'a self,
+		f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+		progress: impl FnMut(usize) -> ControlFlow<()>,
+	) -> Option<DFA<R>>
+	where
+		R: Clone + Ord + Hash,
+	{
+		self.determinize_generic(
+			explored(f),
+			|det_q| det_q.iter().any(|q| self.final_states.contains(q)),
+			progress,
+		)
+	}
+
+	/// Turns this NFA into a DFA, deciding which deterministic states are
+	/// final using the given `is_final` predicate.
+	///
+	/// The predicate is called once for each subset of NFA states (modulo
+	/// epsilon transitions) reached during determinization, instead of
+	/// checking if the subset contains a final state of `self`. This allows
+	/// custom acceptance conditions, such as requiring a specific state to be
+	/// reached. The final states of `self` are ignored.
+	///
+	/// See [`Self::determinize`] for the requirements on `f`.
+	pub fn determinize_with_final<'a, R>(
+		&'a self,
+		f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+		is_final: impl Fn(&BTreeSet<&Q>) -> bool,
+	) -> DFA<R>
+	where
+		R: Clone + Ord + Hash,
+	{
+		self.determinize_generic(explored(f), is_final, |_| ControlFlow::Continue(()))
+			.unwrap()
+	}
+
+	fn determinize_generic<'a, R>(
+		&'a self,
+		resolve: impl FnMut(&BTreeSet<&'a Q>) -> Resolved<R>,
+		is_final: impl Fn(&BTreeSet<&Q>) -> bool,
+		progress: impl FnMut(usize) -> ControlFlow<()>,
+	) -> Option<DFA<R>>
+	where
+		R: Clone + Ord + Hash,
+	{
+		self.determinize_generic_with_hasher(resolve, is_final, progress, RandomState::new(), None)
+	}
+
+	/// Turns this NFA into a DFA, using the given hasher to keep track of the
+	/// visited deterministic states.
+	///
+	/// The default hasher of [`HashSet`] is resistant to collision attacks,
+	/// but slow on the small keys created during determinization. A faster
+	/// hasher can be used instead when `f` only produces trusted states. The
+	/// result does not depend on the hasher.
+	///
+	/// See [`Self::determinize`] for the requirements on `f`.
+	pub fn determinize_with_hasher<'a, R, S>(
+		&'a self,
+		f: impl FnMut(&BTreeSet<&'a Q>) -> R,
+		hash_builder: S,
+	) -> DFA<R>
+	where
+		R: Clone + Ord + Hash,
+		S: BuildHasher,
+	{
+		self.determinize_generic_with_hasher(
+			explored(f),
+			|det_q| det_q.iter().any(|q| self.final_states.contains(q)),
+			|_| ControlFlow::Continue(()),
+			hash_builder,
+			None,
+		)
+		.unwrap()
+	}
+
+	/// Subset construction loop shared by every determinization method.
+	///
+	/// The `resolve` function gives the deterministic state of each subset of
+	/// NFA states. A subset resolved as [`Resolved::Sink`] is not explored:
+	/// its deterministic state is made final and loops on any character.
+	///
+	/// If a `dead` state is given, the characters not covered by the
+	/// transitions of a state lead to it, making the result complete. It is
+	/// not final, and loops on any character.
+	fn determinize_generic_with_hasher<'a, R, S>(
+		&'a self,
+		mut resolve: impl FnMut(&BTreeSet<&'a Q>) -> Resolved<R>,
+		is_final: impl Fn(&BTreeSet<&Q>) -> bool,
+		mut progress: impl FnMut(usize) -> ControlFlow<()>,
+		hash_builder: S,
+		dead: Option<R>,
+	) -> Option<DFA<R>>
+	where
+		R: Clone + Ord + Hash,
+		S: BuildHasher,
+	{
+		let mut transitions = BTreeMap::new();
+		let mut final_states = BTreeSet::new();
+		let mut sinks = BTreeSet::new();
+
+		// States are marked as visited when first reached, so that each subset
+		// is pushed at most once and freed once explored.
+		let mut visited_states = HashSet::with_hasher(hash_builder);
+		let mut stack = Vec::new();
+		let mut explored = 0;
+
+		#[cfg(debug_assertions)]
+		let mut subsets: HashMap<R, BTreeSet<&Q>> = HashMap::new();
+		let mut reach = |det_q: BTreeSet<&'a Q>, r: &R, stack: &mut Vec<_>| {
+			#[cfg(debug_assertions)]
+			assert!(
+				*subsets.entry(r.clone()).or_insert_with(|| det_q.clone()) == det_q,
+				"`determinize` state function is not injective"
+			);
+
+			if visited_states.insert(r.clone()) {
+				stack.push((det_q, r.clone()))
+			}
+		};
+
+		// create the initial deterministic state.
+		let initial_det_q = self.modulo_epsilon_state(&self.initial_states);
+		let initial_state = match resolve(&initial_det_q) {
+			Resolved::Explored(r) => {
+				reach(initial_det_q, &r, &mut stack);
+				r
+			}
+			Resolved::Sink(r) => {
+				sinks.insert(r.clone());
+				r
+			}
+		};
+
+		while let Some((det_q, r)) = stack.pop() {
+			explored += 1;
+			if progress(explored).is_break() {
+				return None;
+			}
+
+			if is_final(&det_q) {
+				final_states.insert(r.clone());
+			}
+
+			let map = self.determinize_transitions_for(&det_q);
+
+			let mut r_map = BTreeMap::new();
+			for (label, next_det_q) in map {
+				match resolve(&next_det_q) {
+					Resolved::Explored(next_r) => {
+						reach(next_det_q, &next_r, &mut stack);
+						r_map.insert(label, next_r);
+					}
+					Resolved::Sink(next_r) => {
+						sinks.insert(next_r.clone());
+						r_map.insert(label, next_r);
+					}
+				}
+			}
+
+			if let Some(dead) = &dead {
+				let mut uncovered = any_char();
+				for range in r_map.keys() {
+					uncovered.remove(*range);
+				}
+
+				r_map.extend(uncovered.iter().map(|range| (*range, dead.clone())));
+			}
+
+			transitions.insert(r, r_map);
+		}
+
+		if let Some(dead) = dead {
+			let r_map = any_char()
+				.iter()
+				.map(|range| (*range, dead.clone()))
+				.collect();
+			transitions.insert(dead, r_map);
+		}
+
+		for r in sinks {
+			let r_map = any_char().iter().map(|range| (*range, r.clone())).collect();
+			transitions.insert(r.clone(), r_map);
+			final_states.insert(r);
+		}
+
+		Some(DFA::from_parts(
+			initial_state,
+			final_states,
+			DetTransitions::from(transitions),
+		))
+	}
+
+	/// Follows the epsilon-transitions from the given threads, recording the
+	/// tags encountered at position `i`.
+	///
+	/// A state already reached by a previous thread is not visited again, so
+	/// that earlier threads take priority.
+	fn tagged_epsilon_closure<'a>(
+		&'a self,
+		threads: Vec<Thread<'a, Q, Vec<(Tag, usize)>>>,
+		i: usize,
+	) -> Vec<Thread<'a, Q, Vec<(Tag, usize)>>> {
+		threads::epsilon_closure(threads, |q, tags| {
+			let tagged = self
+				.tagged_transitions
+				.get(q)
+				.into_iter()
+				.flatten()
+				.map(|(r, tag)| {
+					let mut r_tags = tags.clone();
+					r_tags.push((*tag, i));
+					(r, r_tags)
+				});
+
+			let untagged = self
+				.transitions
+				.get(q)
+				.and_then(|t| t.get(&None))
+				.into_iter()
+				.flatten()
+				.map(|r| (r, tags.clone()));
+
+			tagged.chain(untagged).collect::<Vec<_>>()
+		})
+	}
+
+	/// Advances the given threads by reading `c`, without following
+	/// epsilon-transitions.
+	fn step_threads<'a, P: Clone>(
+		&'a self,
+		threads: Vec<Thread<'a, Q, P>>,
+		c: char,
+	) -> Vec<Thread<'a, Q, P>> {
+		threads::step(threads, |q, payload| {
+			self.successors(q)
+				.filter(|(label, _)| label.as_ref().map_or(false, |label| label.contains(c)))
+				.flat_map(|(_, targets)| targets)
+				.map(|r| (r, payload.clone()))
+				.collect::<Vec<_>>()
+		})
+	}
+
+	/// Matches the given input, returning the tags encountered along an
+	/// accepting path with the (byte) position at which each tag fired.
+	///
+	/// Returns `None` if the input is not recognized by the automaton.
+	pub fn match_with_tags(&self, input: &str) -> Option<Vec<(Tag, usize)>> {
+		let mut threads = self.tagged_epsilon_closure(
+			self.initial_states
+				.iter()
+				.map(|q| (q, Vec::new()))
+				.collect(),
+			0,
+		);
+
+		for (i, c) in input.char_indices() {
+			let next_threads = self.step_threads(threads, c);
+			threads = self.tagged_epsilon_closure(next_threads, i + c.len_utf8());
+		}
+
+		threads
+			.into_iter()
+			.find(|(q, _)| self.is_final_state(q))
+			.map(|(_, tags)| tags)
+	}
+
+	/// Finds the prefix of `input` matched with leftmost-first semantics,
+	/// returning its (byte) length.
+	///
+	/// Paths are ordered by the order of the tagged epsilon-transitions they
+	/// follow, as built by [`Self::with_priorities`]. The match of the first
+	/// path that reaches a final state is preferred over the matches of the
+	/// following paths, even if they are longer. For instance, `a|ab` matches
+	/// only `a` in `ab`. Returns `None` if no prefix of `input` is
+	/// recognized.
+	pub fn match_first(&self, input: &str) -> Option<usize> {
+		let mut threads = self.tagged_epsilon_closure(
+			self.initial_states
+				.iter()
+				.map(|q| (q, Vec::new()))
+				.collect(),
+			0,
+		);
+
+		let mut result = None;
+		let mut offset = 0;
+		let mut chars = input.chars();
+		loop {
+			// a match cuts off every lower priority thread.
+			if let Some(k) = threads.iter().position(|(q, _)| self.is_final_state(q)) {
+				result = Some(offset);
+				threads.truncate(k);
+			}
+
+			let Some(c) = chars.next() else { break };
+			if threads.is_empty() {
+				break;
+			}
+
+			offset += c.len_utf8();
+			let next_threads = self.step_threads(threads, c);
+			threads = self.tagged_epsilon_closure(next_threads, offset);
+		}
+
+		result
+	}
+
+	/// Returns the states that are both reachable from an initial state and
+	/// co-reachable from a final state.
+	fn productive_states(&self) -> BTreeSet<&Q> {
+		// states reachable from an initial state.
+		let mut reachable = BTreeSet::new();
+		let mut stack: Vec<_> = self.initial_states.iter().collect();
+		while let Some(q) = stack.pop() {
+			if reachable.insert(q) {
+				for (_, targets) in self.successors(q) {
+					stack.extend(targets)
+				}
+			}
+		}
+
+		// states co-reachable from a final state.
+		let mut predecessors: BTreeMap<&Q, Vec<&Q>> = BTreeMap::new();
+		for (q, q_transitions) in &self.transitions {
+			for r in q_transitions.values().flatten() {
+				predecessors.entry(r).or_default().push(q)
+			}
+		}
+
+		let mut productive = BTreeSet::new();
+		let mut stack: Vec<_> = self
+			.final_states
+			.iter()
+			.filter(|q| reachable.contains(q))
+			.collect();
+		while let Some(q) = stack.pop() {
+			if productive.insert(q) {
+				if let Some(q_predecessors) = predecessors.get(q) {
+					stack.extend(q_predecessors.iter().filter(|p| reachable.contains(*p)))
+				}
+			}
+		}
+
+		productive
+	}
+
+	/// Returns the sub-automaton restricted to the productive states, the
+	/// states that are both reachable from an initial state and co-reachable
+	/// from a final state.
+	///
+	/// The resulting automaton recognizes the same language, but is generally
+	/// faster to determinize.
+	pub fn productive(&self) -> NFA<Q>
+	where
+		Q: Clone,
+	{
+		let productive = self.productive_states();
+
+		let mut result = NFA::new();
+
+		for q in &productive {
+			result.add_state((*q).clone());
+
+			for (label, targets) in self.successors(q) {
+				for r in targets {
+					if productive.contains(r) {
+						result.add((*q).clone(), label.clone(), r.clone())
+					}
+				}
+			}
+
+			if let Some(tagged_transitions) = self.tagged_transitions.get(q) {
+				for (r, tag) in tagged_transitions {
+					if productive.contains(r) {
+						result
+							.tagged_transitions
+							.entry((*q).clone())
+							.or_default()
+							.push((r.clone(), *tag))
+					}
+				}
+			}
+		}
+
+		for q in &self.initial_states {
+			if productive.contains(q) {
+				result.add_initial_state(q.clone());
+			}
+		}
+
+		for q in &self.final_states {
+			if productive.contains(q) {
+				result.add_final_state(q.clone());
+			}
+		}
+
+		result
+	}
+
+	/// Builds the automaton used to search for matches anchored at the end of
+	/// the input (as with `pat$`).
+	///
+	/// The result recognizes the reversed language of this automaton: every
+	/// transition is flipped, and the initial and final states are swapped.
+	/// Reading the input backward, its longest recognized prefix is hence the
+	/// longest recognized suffix of the input, found with
+	/// [`MatchMode::Suffix`](crate::MatchMode::Suffix), in a single pass over
+	/// the input. States are numbered following the order of `Q`.
+	pub fn anchored_end(&self) -> NFA<u32> {
+		let mut states: BTreeSet<&Q> = self.transitions.keys().collect();
+		states.extend(&self.initial_states);
+		states.extend(&self.final_states);
+		let index: BTreeMap<&Q, u32> = states.into_iter().zip(0..).collect();
+
+		let mut result = NFA::new();
+		for &i in index.values() {
+			result.add_state(i)
+		}
+
+		for (q, label, r) in self.edges() {
+			result.add(index[r], label.clone(), index[q])
+		}
+
+		for q in &self.final_states {
+			result.add_initial_state(index[q]);
+		}
+
+		for q in &self.initial_states {
+			result.add_final_state(index[q]);
+		}
+
+		result
+	}
+
+	/// Rewrites every non-epsilon transition label through `f`, leaving
+	/// epsilon-transitions intact.
+	///
+	/// Note that `f` may produce overlapping labels for transitions leaving the
+	/// same state, introducing nondeterminism.
+	pub fn map_labels(&self, f: impl Fn(&RangeSet<char>) -> RangeSet<char>) -> NFA<Q>
+	where
+		Q: Clone,
+	{
+		let mut transitions = BTreeMap::new();
+
+		for (q, q_transitions) in &self.transitions {
+			let mut mapped_transitions: Transitions<Q> = BTreeMap::new();
+
+			for (label, targets) in q_transitions {
+				mapped_transitions
+					.entry(label.as_ref().map(&f))
+					.or_default()
+					.extend(targets.iter().cloned());
+			}
+
+			transitions.insert(q.clone(), mapped_transitions);
+		}
+
+		NFA {
+			transitions,
+			tagged_transitions: self.tagged_transitions.clone(),
+			initial_states: self.initial_states.clone(),
+			final_states: self.final_states.clone(),
+		}
+	}
+
+	/// Restricts the automaton to the given alphabet.
+	///
+	/// Every transition label is intersected with `allowed`, transitions with
+	/// an empty intersection being removed. Epsilon-transitions are preserved.
+	pub fn restrict_alphabet(&self, allowed: &RangeSet<char>) -> NFA<Q>
+	where
+		Q: Clone,
+	{
+		let mut result = self.map_labels(|label| charset_intersection(label, allowed));
+
+		for q_transitions in result.transitions.values_mut() {
+			q_transitions.retain(|label, _| label.as_ref().map_or(true, |l| !l.is_empty()))
+		}
+
+		result
+	}
+
+	/// Adds the given `other` automaton to `self`, mapping the other automaton
+	/// states in the process.
+	///
+	/// The mapped states of `other` are merged with the states of `self`
+	/// sharing the same identifier. To compute the union of the recognized
+	/// languages, `f` must map the states of `other` to states that are not
+	/// used by `self`.
+	pub fn mapped_union<R>(&mut self, other: NFA<R>, f: impl Fn(R) -> Q) {
+		for (q, transitions) in other.transitions {
+			let this_transitions = self.transitions.entry(f(q)).or_default();
+			for (label, targets) in transitions {
+				this_transitions
+					.entry(label)
+					.or_default()
+					.extend(targets.into_iter().map(&f));
+			}
+		}
+
+		for (q, tagged_transitions) in other.tagged_transitions {
+			self.tagged_transitions
+				.entry(f(q))
+				.or_default()
+				.extend(tagged_transitions.into_iter().map(|(r, tag)| (f(r), tag)));
+		}
+
+		self.initial_states
+			.extend(other.initial_states.into_iter().map(&f));
+		self.final_states
+			.extend(other.final_states.into_iter().map(f));
+	}
+
+	/// Adds the given `other` automaton to `self`, so that `self` recognizes
+	/// the union of both languages.
+	///
+	/// The state spaces of both automata must be disjoint, which is the case if
+	/// they were built using the same [`StateBuilder`]. Otherwise states with
+	/// the same identifier would be merged, and the resulting language would
+	/// be incorrect. This is checked in debug builds.
+	pub fn union(&mut self, other: NFA<Q>) {
+		debug_assert!(
+			other
+				.transitions
+				.keys()
+				.chain(&other.initial_states)
+				.chain(&other.final_states)
+				.all(|q| !self.transitions.contains_key(q)
+					&& !self.initial_states.contains(q)
+					&& !self.final_states.contains(q)),
+			"`union` operands are not disjoint"
+		);
+
+		self.mapped_union(other, |q| q)
+	}
+
+	/// Computes the product between `self` and `other`.
+	///
+	/// The input function `f` computes the product between two states.
+	pub fn product<'a, 'b, R, S>(
+		&'a self,
+		other: &'b NFA<R>,
+		mut f: impl FnMut(&'a Q, &'b R) -> S,
+	) -> NFA<S>
+	where
+		R: Ord,
+		S: Clone + Ord + Hash,
+	{
+		let mut result = NFA::new();
+
+		let mut stack = Vec::with_capacity(self.initial_states.len() * other.initial_states.len());
+		for a in &self.initial_states {
+			for b in &other.initial_states {
+				let q = f(a, b);
+				stack.push((q.clone(), a, b));
+				result.add_initial_state(q);
+			}
+		}
+
+		let mut visited = HashSet::new();
+		while let Some((q, a, b)) = stack.pop() {
+			if visited.insert(q.clone()) {
+				if self.is_final_state(a) && other.is_final_state(b) {
+					result.add_final_state(q.clone());
+				}
+
+				let transitions = result.transitions.entry(q).or_default();
+
+				let a_labeled: Vec<_> = self
+					.successors(a)
+					.filter_map(|(label, successors)| Some((label.as_ref()?, successors)))
+					.collect();
+				let b_labeled: Vec<_> = other
+					.successors(b)
+					.filter_map(|(label, successors)| Some((label.as_ref()?, successors)))
+					.collect();
+
+				let overlaps = label_overlaps(
+					a_labeled.iter().map(|(label, _)| *label),
+					b_labeled.iter().map(|(label, _)| *label),
+				);
+
+				for ((i, j), label) in overlaps {
+					let successors = transitions.entry(Some(label)).or_default();
+
+					for sa in a_labeled[i].1 {
+						for sb in b_labeled[j].1 {
+							let s = f(sa, sb);
+							stack.push((s.clone(), sa, sb));
+							successors.insert(s);
+						}
+					}
+				}
+
+				if let Some(a_successors) = self.transitions.get(a).and_then(|s| s.get(&None)) {
+					if let Some(b_successors) = other.transitions.get(b).and_then(|s| s.get(&None))
+					{
+						let successors = transitions.entry(None).or_default();
+
+						for sa in a_successors {
+							for sb in b_successors {
+								let s = f(sa, sb);
+								stack.push((s.clone(), sa, sb));
+								successors.insert(s);
+							}
+						}
+					}
+				}
+			}
+		}
+
+		result
+	}
+}
+
+impl NFA<u32> {
+	/// Builds an automaton recognizing any string containing a match of
+	/// `pattern` as a factor, meaning `.*pattern.*`.
+	///
+	/// The states of `pattern` are renumbered using `state_builder`.
+	pub fn containing<Q: Ord>(
+		pattern: &NFA<Q>,
+		state_builder: &mut U32StateBuilder,
+	) -> Result<Self, TooManyStates> {
+		let mut result = NFA::new();
+
+		let start = state_builder.next_state(&mut result)?;
+		result.add(start, Some(any_char()), start);
+		result.add_initial_state(start);
+
+		let end = state_builder.next_state(&mut result)?;
+		result.add(end, Some(any_char()), end);
+		result.add_final_state(end);
+
+		let mut map = BTreeMap::new();
+		for q in pattern
+			.transitions
+			.keys()
+			.chain(&pattern.initial_states)
+			.chain(&pattern.final_states)
+		{
+			if !map.contains_key(q) {
+				map.insert(q, state_builder.next_state(&mut result)?);
+			}
+		}
+
+		for (q, label, r) in pattern.edges() {
+			result.add(map[q], label.clone(), map[r])
+		}
+
+		for q in &pattern.initial_states {
+			result.add(start, None, map[q])
+		}
+
+		for q in &pattern.final_states {
+			result.add(map[q], None, end)
+		}
+
+		Ok(result)
+	}
+
+	/// Adds the given `other` automaton to `self`, so that `self` recognizes
+	/// the union of both languages.
+	///
+	/// Unlike [`Self::union`], the states of `other` do not need to be
+	/// disjoint from the states of `self`: they are renumbered using
+	/// `state_builder`, which must be the builder used to create the states of
+	/// `self`.
+	pub fn union_with_builder(
+		&mut self,
+		other: NFA<u32>,
+		state_builder: &mut U32StateBuilder,
+	) -> Result<(), TooManyStates> {
+		let mut map = BTreeMap::new();
+		for q in other
+			.transitions
+			.keys()
+			.chain(&other.initial_states)
+			.chain(&other.final_states)
+		{
+			if !map.contains_key(q) {
+				map.insert(*q, state_builder.next_state(self)?);
+			}
+		}
+
+		self.mapped_union(other, |q| map[&q]);
+		Ok(())
+	}
+}
+
+/// Computes the intersection of every pair of labels from `a` and `b`,
+/// indexed by the position of the labels in their respective lists.
+///
+/// Empty intersections are omitted. Instead of intersecting every pair of
+/// labels, the ranges of both lists are swept together so that only actually
+/// overlapping labels are considered. This takes `O(n log n + k log n)` time,
+/// where `n` is the total number of ranges and `k` the number of
+/// (segment, label pair) overlaps, a segment being the interval between two
+/// consecutive range bounds. This is not linear: in the worst case, when
+/// every label of `a` overlaps every label of `b` on many segments, `k` is
+/// as large as the number of range pairs intersected by the naive approach.
+fn label_overlaps<'l>(
+	a: impl IntoIterator<Item = &'l RangeSet<char>>,
+	b: impl IntoIterator<Item = &'l RangeSet<char>>,
+) -> BTreeMap<(usize, usize), RangeSet<char>> {
+	// `(position, is_end, is_b, label index)`, ends are exclusive.
+	let mut events = Vec::new();
+	let a = a
+		.into_iter()
+		.enumerate()
+		.map(|(i, label)| (false, i, label));
+	let b = b.into_iter().enumerate().map(|(i, label)| (true, i, label));
+	for (is_b, i, label) in a.chain(b) {
+		for range in label.iter() {
+			if let (Some(first), Some(last)) = (range.first(), range.last()) {
+				events.push((first as u32, false, is_b, i));
+				events.push((last as u32 + 1, true, is_b, i));
+			}
+		}
+	}
+
+	events.sort_unstable();
+
+	let mut result: BTreeMap<(usize, usize), RangeSet<char>> = BTreeMap::new();
+	let mut active: [BTreeMap<usize, usize>; 2] = [BTreeMap::new(), BTreeMap::new()];
+	let mut events = events.into_iter().peekable();
+	while let Some((position, is_end, is_b, i)) = events.next() {
+		let count = active[is_b as usize].entry(i).or_default();
+		if is_end {
+			*count -= 1;
+			if *count == 0 {
+				active[is_b as usize].remove(&i);
+			}
+		} else {
+			*count += 1;
+		}
+
+		if let Some((next, ..)) = events.peek() {
+			if *next > position && !active[0].is_empty() && !active[1].is_empty() {
+				// skip the surrogate code points.
+				let first = if (0xd800..0xe000).contains(&position) {
+					0xe000
+				} else {
+					position
+				};
+				let last = if (0xd800..0xe000).contains(&(next - 1)) {
+					0xd7ff
+				} else {
+					next - 1
+				};
+
+				if first <= last {
+					let first = char::from_u32(first).unwrap();
+					let last = char::from_u32(last).unwrap();
+					for &i in active[0].keys() {
+						for &j in active[1].keys() {
+							result.entry((i, j)).or_default().insert(first..=last)
+						}
+					}
+				}
+			}
+		}
+	}
+
+	result
+}
+
+/// Iterator over the successors of a given state in a [`NFA`].
+pub struct Successors<'a, Q> {
+	inner: Option<std::collections::btree_map::Iter<'a, Option<RangeSet<char>>, BTreeSet<Q>>>,
+}
+
+impl<'a, Q> Successors<'a, Q> {
+	pub fn new(map: Option<&'a BTreeMap<Option<RangeSet<char>>, BTreeSet<Q>>>) -> Self {
+		Self {
+			inner: map.map(|map| map.iter()),
+		}
+	}
+}
+
+impl<'a, Q> Iterator for Successors<'a, Q> {
+	type Item = (&'a Option<RangeSet<char>>, &'a BTreeSet<Q>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.as_mut().and_then(|inner| inner.next())
+	}
+}
+
+impl<Q: Ord + Hash> NFA<Q> {
+	/// Checks if the automaton recognizes the given UTF-16 encoded input.
+	///
+	/// Surrogate pairs are decoded into a single character. An input
+	/// containing a lone surrogate is never recognized.
+	pub fn accepts_utf16(&self, input: &[u16]) -> bool {
+		let mut matcher = Matcher::new(self);
+
+		for c in char::decode_utf16(input.iter().copied()) {
+			match c {
+				Ok(c) if matcher.feed(c) => (),
+				_ => return false,
+			}
+		}
+
+		matcher.is_match()
+	}
+
+	/// Precomputes the epsilon-closure of every state, returning an
+	/// equivalent automaton that is faster to run.
+	///
+	/// The closures are computed once for all, in time proportional to the
+	/// number of states times the number of epsilon-transitions. The result
+	/// borrows `self` and should be kept around to match many inputs.
+	pub fn with_epsilon_closures(&self) -> ClosedNfa<'_, Q> {
+		let mut closures = HashMap::new();
+
+		for (q, _, r) in self.edges() {
+			for q in [q, r] {
+				if !closures.contains_key(q) {
+					closures.insert(q, self.modulo_epsilon_state(Some(q)).into_iter().collect());
+				}
+			}
+		}
+
+		for q in self.initial_states.iter().chain(&self.final_states) {
+			closures
+				.entry(q)
+				.or_insert_with(|| self.modulo_epsilon_state(Some(q)).into_iter().collect());
+		}
+
+		ClosedNfa {
+			nfa: self,
+			initial_states: self
+				.modulo_epsilon_state(&self.initial_states)
+				.into_iter()
+				.collect(),
+			closures,
+		}
+	}
+
+	/// Checks if the automaton recognizes `input`, expanding at most
+	/// `max_steps` states.
+	///
+	/// A step is the expansion of one live state: following its transitions
+	/// on the current character, or its epsilon-transitions. Steps are
+	/// counted across the whole input, so that the total matching work is
+	/// bounded by `max_steps` times the out-degree of the states, whatever
+	/// the size of the live state sets. Returns [`BudgetExceeded`] as soon as
+	/// the budget is exhausted.
+	pub fn accepts_within(&self, input: &str, max_steps: usize) -> Result<bool, BudgetExceeded> {
+		let mut steps = 0;
+		let mut states =
+			self.budgeted_epsilon_closure(self.initial_states.iter(), &mut steps, max_steps)?;
+
+		for c in input.chars() {
+			if states.is_empty() {
+				return Ok(false);
+			}
+
+			let mut next_states = Vec::new();
+			for q in states {
+				steps += 1;
+				if steps > max_steps {
+					return Err(BudgetExceeded);
+				}
+
+				for (label, targets) in self.transitions.get(q).into_iter().flatten() {
+					if label.as_ref().map_or(false, |label| label.contains(c)) {
+						next_states.extend(targets)
+					}
+				}
+			}
+
+			states = self.budgeted_epsilon_closure(next_states, &mut steps, max_steps)?;
+		}
+
+		Ok(states.iter().any(|q| self.final_states.contains(q)))
+	}
+
+	/// Computes the epsilon-closure of the given states, counting each
+	/// expanded state as a step.
+	fn budgeted_epsilon_closure<'a>(
+		&'a self,
+		states: impl IntoIterator<Item = &'a Q>,
+		steps: &mut usize,
+		max_steps: usize,
+	) -> Result<Vec<&'a Q>, BudgetExceeded> {
+		let threads = states.into_iter().map(|q| (q, ())).collect();
+		let closure = threads::try_epsilon_closure(threads, |q, ()| {
+			*steps += 1;
+			if *steps > max_steps {
+				return Err(BudgetExceeded);
+			}
+
+			Ok(self
+				.transitions
+				.get(q)
+				.and_then(|t| t.get(&None))
+				.into_iter()
+				.flatten()
+				.map(|r| (r, ()))
+				.collect::<Vec<_>>())
+		})?;
+
+		Ok(closure.into_iter().map(|(q, ())| q).collect())
+	}
+}
+
+impl<Q: Ord + Hash> Automaton<char> for NFA<Q> {
+	type State<'a>
+		= VisitingState<'a, Q>
+	where
+		Self: 'a;
+
+	fn initial_state(&self) -> Option<Self::State<'_>> {
+		self.reset_state(VisitingState {
+			states: HashSet::new(),
+			next_states: HashSet::new(),
+			stack: Vec::new(),
+			closures: HashMap::new(),
+		})
+	}
+
+	fn reset_state<'a>(
+		&'a self,
+		VisitingState {
+			mut states,
+			mut next_states,
+			mut stack,
+			closures,
+		}: Self::State<'a>,
+	) -> Option<Self::State<'a>> {
+		states.clear();
+		next_states.clear();
+		stack.clear();
+
+		for r in &self.initial_states {
+			states.insert(r);
+			stack.push(r);
+		}
+
+		// epsilon-closure.
+		while let Some(q) = stack.pop() {
+			if let Some(q_transitions) = self.transitions.get(q) {
+				if let Some(targets) = q_transitions.get(&None) {
+					for r in targets {
+						if states.insert(r) {
+							stack.push(r);
+						}
+					}
+				}
+			}
+		}
+
+		if states.is_empty() {
+			None
+		} else {
+			Some(VisitingState {
+				states,
+				next_states,
+				stack,
+				closures,
+			})
+		}
+	}
+
+	fn next_state<'a>(
+		&'a self,
+		VisitingState {
+			mut states,
+			mut next_states,
+			stack,
+			mut closures,
+		}: Self::State<'a>,
+		token: char,
+	) -> Option<Self::State<'a>> {
+		for &q in &states {
+			if let Some(q_transitions) = self.transitions.get(q) {
+				for (label, targets) in q_transitions {
+					if label.as_ref().map_or(false, |label| label.contains(token)) {
+						for r in targets {
+							// epsilon-closure, computed once per state.
+							let closure = closures.entry(r).or_insert_with(|| {
+								self.modulo_epsilon_state(Some(r)).into_iter().collect()
+							});
+
+							next_states.extend(closure.iter().copied())
+						}
+					}
+				}
+			}
+		}
+
+		if next_states.is_empty() {
+			None
+		} else {
+			states.clear();
+			Some(VisitingState {
+				states: next_states,
+				next_states: states,
+				stack,
+				closures,
+			})
+		}
+	}
+
+	fn is_final_state<'a>(&'a self, VisitingState { states, .. }: &Self::State<'a>) -> bool {
+		for &q in states {
+			if self.final_states.contains(q) {
+				return true;
+			}
+		}
+
+		false
+	}
+}
+
+/// State of an [`NFA`] run, as a set of NFA states.
+///
+/// The epsilon-closure of each NFA state is computed the first time the
+/// state is reached, and kept along the run (including across
+/// [`Automaton::reset_state`]) so that later steps only look it up.
+pub struct VisitingState<'a, Q> {
+	states: HashSet<&'a Q>,
+	next_states: HashSet<&'a Q>,
+	stack: Vec<&'a Q>,
+	closures: HashMap<&'a Q, Vec<&'a Q>>,
+}
+
+/// NFA with precomputed epsilon-closures.
+///
+/// The [`Automaton`] implementation of [`NFA`] computes epsilon-closures
+/// lazily, caching them in the [`VisitingState`] of each run. This wrapper
+/// computes the closure of every state once and shares it between all runs,
+/// which is faster when the same automaton runs on many short inputs and
+/// has many epsilon-transitions.
+///
+/// See [`NFA::with_epsilon_closures`].
+pub struct ClosedNfa<'a, Q> {
+	nfa: &'a NFA<Q>,
+	initial_states: Vec<&'a Q>,
+	closures: HashMap<&'a Q, Vec<&'a Q>>,
+}
+
+impl<'a, Q: Ord + Hash> ClosedNfa<'a, Q> {
+	/// Returns the underlying automaton.
+	pub fn nfa(&self) -> &'a NFA<Q> {
+		self.nfa
+	}
+
+	/// Returns the epsilon-closure of the given state, including itself.
+	pub fn closure(&self, q: &Q) -> &[&'a Q] {
+		self.closures.get(q).map_or(&[], Vec::as_slice)
+	}
+}
+
+impl<'c, Q: Ord + Hash> Automaton<char> for ClosedNfa<'c, Q> {
+	type State<'a>
+		= VisitingState<'c, Q>
+	where
+		Self: 'a;
+
+	fn initial_state(&self) -> Option<Self::State<'_>> {
+		if self.initial_states.is_empty() {
+			None
+		} else {
+			Some(VisitingState {
+				states: self.initial_states.iter().copied().collect(),
+				next_states: HashSet::new(),
+				stack: Vec::new(),
+				closures: HashMap::new(),
+			})
+		}
+	}
+
+	fn next_state<'a>(
+		&'a self,
+		VisitingState {
+			mut states,
+			mut next_states,
+			stack,
+			closures,
+		}: Self::State<'a>,
+		token: char,
+	) -> Option<Self::State<'a>> {
+		for &q in &states {
+			for (label, targets) in self.nfa.transitions.get(q).into_iter().flatten() {
+				if label.as_ref().map_or(false, |label| label.contains(token)) {
+					for r in targets {
+						next_states.extend(self.closure(r))
+					}
+				}
+			}
+		}
+
+		if next_states.is_empty() {
+			None
+		} else {
+			states.clear();
+			Some(VisitingState {
+				states: next_states,
+				next_states: states,
+				stack,
+				closures,
+			})
+		}
+	}
+
+	fn is_final_state<'a>(&'a self, VisitingState { states, .. }: &Self::State<'a>) -> bool {
+		states.iter().any(|q| self.nfa.final_states.contains(q))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{DynMatcher, StepResult};
+
+	fn accepts<A: Automaton<char>>(aut: &A, input: &str) -> bool {
+		let mut state = match aut.initial_state() {
+			Some(state) => state,
+			None => return false,
+		};
+
+		for c in input.chars() {
+			state = match aut.next_state(state, c) {
+				Some(next_state) => next_state,
+				None => return false,
+			}
+		}
+
+		aut.is_final_state(&state)
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	#[should_panic(expected = "not injective")]
+	fn determinize_non_injective() {
+		let nfa: NFA = NFA::from_chars(['a'], U32StateBuilder::default()).unwrap();
+		nfa.determinize(|_| 0);
+	}
+
+	#[test]
+	fn reset_state() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['a']), Quantifier::Star)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		let mut state = nfa.initial_state().unwrap();
+		for _ in 0..3 {
+			state = nfa.next_state(state, 'a').unwrap();
+		}
+
+		let capacity = (state.states.capacity(), state.next_states.capacity());
+		let state = nfa.reset_state(state).unwrap();
+		assert_eq!(
+			(state.states.capacity(), state.next_states.capacity()),
+			capacity
+		);
+
+		let initial_state = nfa.initial_state().unwrap();
+		assert_eq!(state.states, initial_state.states);
+		assert!(Automaton::is_final_state(&nfa, &state));
+	}
+
+	#[test]
+	fn map_labels() {
+		let nfa: NFA = NFA::from_chars(['a', '1'], U32StateBuilder::default()).unwrap();
+		let nfa = nfa.map_labels(|label| {
+			let mut result = RangeSet::new();
+
+			if label.contains('a') {
+				result.insert('a'..='z')
+			}
+
+			if label.contains('1') {
+				result.insert('0'..='9')
+			}
+
+			result
+		});
+
+		assert!(accepts(&nfa, "a1"));
+		assert!(accepts(&nfa, "z7"));
+		assert!(!accepts(&nfa, "1a"));
+	}
+
+	#[test]
+	fn determinize_observed() {
+		let nfa: NFA = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+
+		let mut calls = 0;
+		let dfa = nfa.determinize_observed(
+			|q| q.iter().copied().copied().collect::<Vec<_>>(),
+			|n| {
+				calls += 1;
+				if n > 2 {
+					ControlFlow::Break(())
+				} else {
+					ControlFlow::Continue(())
+				}
+			},
+		);
+
+		assert!(dfa.is_none());
+		assert_eq!(calls, 3);
+
+		let dfa = nfa.determinize_observed(
+			|q| q.iter().copied().copied().collect::<Vec<_>>(),
+			|_| ControlFlow::Continue(()),
+		);
+		assert!(dfa.is_some());
+	}
+
+	#[test]
+	fn successor_labels() {
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, Some(RangeSet::from_iter(['a'..='c'])), 1);
+		nfa.add(0, Some(RangeSet::from_iter(['x'..='z'])), 1);
+		nfa.add(0, Some(RangeSet::from_iter(['b'..='d'])), 2);
+		nfa.add(0, None, 2);
+
+		let labels = nfa.successor_labels(&0);
+		assert_eq!(labels.len(), 2);
+		assert_eq!(labels[&1], RangeSet::from_iter(['a'..='c', 'x'..='z']));
+		assert_eq!(labels[&2], RangeSet::from_iter(['b'..='d']));
+		assert!(nfa.successor_labels(&1).is_empty());
+	}
+
+	#[test]
+	fn predecessors() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(['a', 'b'].into_iter().collect(), Quantifier::Star),
+				(['c'].into_iter().collect(), Quantifier::Optional),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let predecessors = nfa.predecessors();
+
+		let mut forward: Vec<_> = nfa.edges().collect();
+		let mut backward: Vec<_> = predecessors
+			.iter()
+			.flat_map(|(r, incoming)| incoming.iter().map(move |(label, q)| (*q, *label, *r)))
+			.collect();
+		forward.sort();
+		backward.sort();
+		assert_eq!(forward, backward);
+	}
+
+	#[test]
+	fn with_epsilon_closures() {
+		// `[ab]*c`, with a long chain of epsilon-transitions.
+		let mut nfa: NFA = NFA::new();
+		for q in 0..20 {
+			nfa.add(q, None, q + 1);
+		}
+		nfa.add(20, Some(['a', 'b'].into_iter().collect()), 0);
+		nfa.add(20, Some(['c'].into_iter().collect()), 21);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(21);
+
+		let closed = nfa.with_epsilon_closures();
+		assert_eq!(closed.closure(&0).len(), 21);
+		assert_eq!(closed.closure(&21), [&21]);
+
+		for input in ["c", "abc", "bac", "aabbabc", "", "ab", "cc", "acb", "d"] {
+			assert_eq!(
+				DynMatcher::is_match(&closed, input),
+				DynMatcher::is_match(&nfa, input),
+				"{input:?}"
+			);
+		}
+		assert!(DynMatcher::is_match(&closed, "babac"));
+		assert_eq!(DynMatcher::find(&closed, "xxabcx"), Some(2..5));
+	}
 
-				for (a_label, a_successors) in self.successors(a) {
-					match a_label {
-						Some(a_label) => {
-							for (b_label, b_successors) in other.successors(b) {
-								if let Some(b_label) = b_label {
-									let label = charset_intersection(a_label, b_label);
-									if !label.is_empty() {
-										let successors =
-											transitions.entry(Some(label)).or_default();
-
-										for sa in a_successors {
-											for sb in b_successors {
-												let s = f(sa, sb);
-												stack.push((s.clone(), sa, sb));
-												successors.insert(s);
-											}
-										}
-									}
-								}
-							}
-						}
-						None => {
-							if let Some(b_successors) =
-								other.transitions.get(b).and_then(|s| s.get(&None))
-							{
-								let successors = transitions.entry(None).or_default();
-
-								for sa in a_successors {
-									for sb in b_successors {
-										let s = f(sa, sb);
-										stack.push((s.clone(), sa, sb));
-										successors.insert(s);
-									}
-								}
-							}
-						}
-					}
+	#[test]
+	fn accepts_within() {
+		// `[ab]*a[ab][ab]`, whose live state set grows on `a`.
+		let ab: RangeSet<char> = ['a', 'b'].into_iter().collect();
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(ab.clone(), Quantifier::Star),
+				(['a'].into_iter().collect(), Quantifier::One),
+				(ab.clone(), Quantifier::One),
+				(ab, Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		let input = "aaaaaaaa";
+		assert_eq!(nfa.accepts_within(input, 1000), Ok(true));
+		assert_eq!(nfa.accepts_within("aaabbb", 1000), Ok(false));
+		assert_eq!(nfa.accepts_within(input, 10), Err(BudgetExceeded));
+
+		let needed = (0..1000)
+			.find(|&n| nfa.accepts_within(input, n).is_ok())
+			.unwrap();
+		assert!(needed > input.len() * 2);
+	}
+
+	#[test]
+	fn determinize_with_hasher() {
+		use std::hash::{BuildHasherDefault, Hasher};
+
+		/// Deliberately weak hasher, colliding a lot.
+		#[derive(Default)]
+		struct XorHasher(u64);
+
+		impl Hasher for XorHasher {
+			fn finish(&self) -> u64 {
+				self.0
+			}
+
+			fn write(&mut self, bytes: &[u8]) {
+				for b in bytes {
+					self.0 ^= *b as u64
 				}
 			}
 		}
 
-		result
+		let ab: RangeSet<char> = ['a', 'b'].into_iter().collect();
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(ab.clone(), Quantifier::Star),
+				(['a'].into_iter().collect(), Quantifier::One),
+				(ab.clone(), Quantifier::One),
+				(ab, Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let f = |q: &BTreeSet<&u32>| q.iter().copied().copied().collect::<Vec<_>>();
+
+		assert_eq!(
+			nfa.determinize_with_hasher(f, BuildHasherDefault::<XorHasher>::default()),
+			nfa.determinize(f)
+		);
 	}
-}
 
-/// Iterator over the successors of a given state in a [`NFA`].
-pub struct Successors<'a, Q> {
-	inner: Option<std::collections::btree_map::Iter<'a, Option<RangeSet<char>>, BTreeSet<Q>>>,
-}
+	#[test]
+	fn determinize_tagged() {
+		let mut state_builder = U32StateBuilder::default();
+		let keyword: NFA = NFA::from_chars("if".chars(), &mut state_builder).unwrap();
+		let ident: NFA = NFA::from_class_sequence(
+			&[(('a'..='z').collect(), Quantifier::Plus)],
+			&mut state_builder,
+		)
+		.unwrap();
 
-impl<'a, Q> Successors<'a, Q> {
-	pub fn new(map: Option<&'a BTreeMap<Option<RangeSet<char>>, BTreeSet<Q>>>) -> Self {
-		Self {
-			inner: map.map(|map| map.iter()),
+		let mut token_of = BTreeMap::new();
+		for q in keyword.final_states() {
+			token_of.insert(*q, "keyword");
+		}
+		for q in ident.final_states() {
+			token_of.insert(*q, "ident");
 		}
+
+		let nfa = NFA::union_all([keyword, ident], &mut state_builder).unwrap();
+		let (dfa, tokens) = nfa.determinize_tagged(
+			|q| q.iter().copied().copied().collect::<Vec<_>>(),
+			|q| token_of.get(q).copied(),
+		);
+
+		assert!(tokens.keys().all(|q| dfa.is_final_state(q)));
+		assert_eq!(tokens.len(), dfa.final_states().len());
+
+		let classify = |input| dfa.run_classify(input, &tokens);
+		assert_eq!(classify("if"), Some(&BTreeSet::from(["ident", "keyword"])));
+		assert_eq!(classify("i"), Some(&BTreeSet::from(["ident"])));
+		assert_eq!(classify("iff"), Some(&BTreeSet::from(["ident"])));
+		assert_eq!(classify(""), None);
+		assert_eq!(classify("if1"), None);
 	}
-}
 
-impl<'a, Q> Iterator for Successors<'a, Q> {
-	type Item = (&'a Option<RangeSet<char>>, &'a BTreeSet<Q>);
+	#[test]
+	fn determinize_with_final() {
+		// `a*b?`, accepting only once `b` has been read.
+		let mut state_builder = U32StateBuilder::default();
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(['a'].into_iter().collect(), Quantifier::Star),
+				(['b'].into_iter().collect(), Quantifier::Optional),
+			],
+			&mut state_builder,
+		)
+		.unwrap();
+		let after_b = *nfa
+			.transitions()
+			.flat_map(|(_, q_transitions)| q_transitions)
+			.find(|(label, _)| label.as_ref().map_or(false, |l| l.contains('b')))
+			.unwrap()
+			.1
+			.iter()
+			.next()
+			.unwrap();
+
+		let f = |q: &BTreeSet<&u32>| q.iter().copied().copied().collect::<Vec<_>>();
+		let dfa = nfa.determinize_with_final(f, |q| q.contains(&after_b));
+
+		assert!(dfa.accepts("b"));
+		assert!(dfa.accepts("aab"));
+		assert!(!dfa.accepts(""));
+		assert!(!dfa.accepts("aa"));
+		assert!(nfa.determinize(f).accepts("aa"));
+	}
 
-	fn next(&mut self) -> Option<Self::Item> {
-		self.inner.as_mut().and_then(|inner| inner.next())
+	#[test]
+	fn determinization_size_estimate() {
+		// `[ab]*a[ab][ab]`, whose DFA has 8 states.
+		let ab: RangeSet<char> = ['a', 'b'].into_iter().collect();
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(ab.clone(), Quantifier::Star),
+				(['a'].into_iter().collect(), Quantifier::One),
+				(ab.clone(), Quantifier::One),
+				(ab, Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		let (dfa, subsets) = nfa.determinize_with_mapping();
+		assert_eq!(dfa.transitions().len(), subsets.len());
+		assert_eq!(nfa.determinization_size_estimate(None), Some(subsets.len()));
+		assert_eq!(
+			nfa.determinization_size_estimate(Some(subsets.len())),
+			Some(subsets.len())
+		);
+		assert_eq!(
+			nfa.determinization_size_estimate(Some(subsets.len() - 1)),
+			None
+		);
 	}
-}
 
-impl<Q: Ord + Hash> Automaton<char> for NFA<Q> {
-	type State<'a> = VisitingState<'a, Q> where Self: 'a;
+	#[test]
+	fn determinize_with_progress() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(['a', 'b'].into_iter().collect(), Quantifier::Star),
+				(['b'].into_iter().collect(), Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let f = |q: &BTreeSet<&u32>| q.iter().copied().copied().collect::<Vec<_>>();
 
-	fn initial_state(&self) -> Option<Self::State<'_>> {
-		let mut stack = Vec::new();
-		let mut states = HashSet::new();
+		let mut reported = Vec::new();
+		let dfa = nfa.determinize_with_progress(f, |n| reported.push(n));
 
-		for r in &self.initial_states {
-			states.insert(r);
-			stack.push(r);
+		assert_eq!(dfa, nfa.determinize(f));
+		assert_eq!(reported, (1..=dfa.transitions().len()).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn determinize_complete() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(RangeSet::from_iter(['a'..='c']), Quantifier::One),
+				(RangeSet::from_iter(['x']), Quantifier::Star),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		let mut ids = HashMap::new();
+		let complete = nfa.determinize_complete(
+			|q| {
+				let len = ids.len();
+				*ids.entry(q.clone()).or_insert(len)
+			},
+			usize::MAX,
+		);
+
+		for q in complete.states() {
+			let mut covered = RangeSet::new();
+			for (range, _) in complete.successors(q) {
+				covered.insert(*range);
+			}
+
+			assert_eq!(covered, any_char())
 		}
 
-		// epsilon-closure.
-		while let Some(q) = stack.pop() {
-			if let Some(q_transitions) = self.transitions.get(q) {
-				if let Some(targets) = q_transitions.get(&None) {
-					for r in targets {
-						if states.insert(r) {
-							stack.push(r);
-						}
-					}
-				}
+		assert!(!complete.is_final_state(&usize::MAX));
+		for input in ["", "a", "bxx", "ax", "xa", "ab", "\u{10ffff}"] {
+			assert_eq!(complete.accepts(input), accepts(&nfa, input), "{input:?}")
+		}
+	}
+
+	#[test]
+	fn determinize_bounded() {
+		// `(a|b)*a(a|b)(a|b)`, whose minimal DFA has 8 states.
+		let mut state_builder = U32StateBuilder::default();
+		let ab: RangeSet<char> = ['a', 'b'].into_iter().collect();
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(ab.clone(), Quantifier::Star),
+				(['a'].into_iter().collect(), Quantifier::One),
+				(ab.clone(), Quantifier::One),
+				(ab, Quantifier::One),
+			],
+			&mut state_builder,
+		)
+		.unwrap();
+
+		let mut ids = HashMap::new();
+		let bounded = nfa.determinize_bounded(
+			3,
+			|q| {
+				let len = ids.len();
+				*ids.entry(q.clone()).or_insert(len)
+			},
+			|| usize::MAX,
+		);
+		assert!(bounded.transitions().len() <= 4);
+
+		let mut ids = HashMap::new();
+		let exact = nfa.determinize_bounded(
+			100,
+			|q| {
+				let len = ids.len();
+				*ids.entry(q.clone()).or_insert(len)
+			},
+			|| usize::MAX,
+		);
+		assert!(!exact.transitions().contains_key(&usize::MAX));
+
+		let mut inputs = vec![String::new()];
+		for _ in 0..6 {
+			let longer: Vec<_> = inputs
+				.iter()
+				.flat_map(|s| [s.clone() + "a", s.clone() + "b"])
+				.collect();
+			inputs.extend(longer);
+		}
+		inputs.sort();
+		inputs.dedup();
+
+		let mut over_approximated = false;
+		for input in &inputs {
+			let expected = accepts(&nfa, input);
+			assert_eq!(exact.accepts(input), expected, "{input:?}");
+
+			if expected {
+				assert!(bounded.accepts(input), "{input:?}")
+			} else if bounded.accepts(input) {
+				over_approximated = true
 			}
 		}
 
-		if states.is_empty() {
-			None
-		} else {
-			Some(VisitingState {
-				states,
-				next_states: HashSet::new(),
-				stack,
-			})
+		assert!(over_approximated);
+	}
+
+	#[test]
+	fn productive() {
+		let mut state_builder = U32StateBuilder::default();
+		let alts = ["ab", "cd"].map(|s| NFA::from_chars(s.chars(), &mut state_builder).unwrap());
+		let mut nfa = NFA::union_all(alts, &mut state_builder).unwrap();
+
+		// non co-reachable state.
+		let dead = state_builder.next_state(&mut nfa).unwrap();
+		let initial_state = *nfa.initial_states.first().unwrap();
+		nfa.add(initial_state, Some(RangeSet::from_iter(['x'])), dead);
+
+		// non reachable state.
+		let unreachable = state_builder.next_state(&mut nfa).unwrap();
+		let final_state = *nfa.final_states().first().unwrap();
+		nfa.add(unreachable, Some(RangeSet::from_iter(['y'])), final_state);
+
+		let productive = nfa.productive();
+		assert_eq!(productive.transitions().len(), nfa.transitions().len() - 2);
+
+		for input in ["ab", "cd", "", "x", "yd", "abc"] {
+			assert_eq!(accepts(&productive, input), accepts(&nfa, input))
 		}
 	}
 
-	fn next_state<'a>(
-		&'a self,
-		VisitingState {
-			mut states,
-			mut next_states,
-			mut stack,
-		}: Self::State<'a>,
-		token: char,
-	) -> Option<Self::State<'_>> {
-		for &q in &states {
-			if let Some(q_transitions) = self.transitions.get(q) {
-				for (label, targets) in q_transitions {
-					if let Some(label) = label {
-						if label.contains(token) {
-							for r in targets {
-								if next_states.insert(r) {
-									stack.push(r);
+	#[test]
+	fn edges() {
+		let mut nfa: NFA = NFA::from_chars(['a', 'b'], U32StateBuilder::default()).unwrap();
+		nfa.add(0, None, 2);
+
+		let a = Some(RangeSet::from_iter(['a']));
+		let b = Some(RangeSet::from_iter(['b']));
+		let edges: Vec<_> = nfa.edges().collect();
+		assert_eq!(edges, [(&0, &None, &2), (&0, &a, &1), (&1, &b, &2)]);
+		assert_eq!(edges.len(), nfa.num_transitions());
+	}
+
+	#[test]
+	fn is_cyclic() {
+		let mut nfa: NFA = NFA::from_chars(['a', 'b'], U32StateBuilder::default()).unwrap();
+		nfa.add(0, None, 2);
+		assert!(!nfa.is_cyclic());
+
+		nfa.add(2, None, 1);
+		assert!(nfa.is_cyclic());
+
+		let nfa: NFA = NFA::from_class_sequence(
+			&[(RangeSet::from_iter(['a']), Quantifier::Plus)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		assert!(nfa.is_cyclic());
+	}
+
+	#[test]
+	fn strongly_connected_components() {
+		// (ab)*c
+		let mut nfa: NFA = NFA::new();
+		nfa.add_initial_state(0);
+		nfa.add(0, Some(RangeSet::from_iter(['a'])), 1);
+		nfa.add(1, Some(RangeSet::from_iter(['b'])), 2);
+		nfa.add(2, None, 0);
+		nfa.add(0, Some(RangeSet::from_iter(['c'])), 3);
+		nfa.add_final_state(3);
+
+		let components = nfa.strongly_connected_components();
+		assert_eq!(components.len(), 2);
+		assert_eq!(components[0], BTreeSet::from_iter([&3]));
+		assert_eq!(components[1], BTreeSet::from_iter([&0, &1, &2]));
+
+		let loops: Vec<_> = components.iter().filter(|c| c.len() > 1).collect();
+		assert_eq!(loops, [&BTreeSet::from_iter([&0, &1, &2])]);
+
+		let nfa: NFA = NFA::from_chars("abc".chars(), U32StateBuilder::default()).unwrap();
+		let components = nfa.strongly_connected_components();
+		assert_eq!(components.len(), 4);
+		assert!(components.iter().all(|c| c.len() == 1));
+	}
+
+	#[cfg(feature = "petgraph")]
+	#[test]
+	fn to_petgraph() {
+		let nfa: NFA = NFA::from_chars(['a', 'b'], U32StateBuilder::default()).unwrap();
+		let graph = nfa.to_petgraph();
+		assert_eq!(graph.node_count(), 3);
+		assert_eq!(graph.edge_count(), nfa.num_transitions());
+		assert!(!petgraph::algo::is_cyclic_directed(&graph));
+	}
+
+	#[test]
+	fn union() {
+		let mut state_builder = U32StateBuilder::default();
+		let mut nfa: NFA = NFA::from_chars("ab".chars(), &mut state_builder).unwrap();
+		let other = NFA::from_chars("cd".chars(), &mut state_builder).unwrap();
+		nfa.union(other);
+
+		assert!(accepts(&nfa, "ab"));
+		assert!(accepts(&nfa, "cd"));
+		assert!(!accepts(&nfa, "ad"));
+		assert!(!accepts(&nfa, "cb"));
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	#[should_panic(expected = "not disjoint")]
+	fn union_overlapping() {
+		let mut nfa: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+		let other = NFA::from_chars("cd".chars(), U32StateBuilder::default()).unwrap();
+		nfa.union(other);
+	}
+
+	#[test]
+	fn determinize_discovery_order() {
+		let ab: RangeSet<char> = ['a', 'b'].into_iter().collect();
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(ab.clone(), Quantifier::Star),
+				(['a'].into_iter().collect(), Quantifier::One),
+				(ab.clone(), Quantifier::One),
+				(ab.clone(), Quantifier::One),
+				(ab, Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		// Numbering states in order of discovery is sensitive to the order in
+		// which subsets are visited.
+		let mut ids = HashMap::new();
+		let numbered = nfa.determinize(|q| {
+			let len = ids.len();
+			*ids.entry(q.iter().copied().copied().collect::<Vec<_>>())
+				.or_insert(len)
+		});
+		let by_subset = nfa.determinize(|q| q.iter().copied().copied().collect::<Vec<_>>());
+
+		assert_eq!(numbered.states().len(), 17);
+		assert_eq!(*numbered.initial_state(), 0);
+		assert_eq!(by_subset.map(|q| ids[q], |l| *l), numbered);
+	}
+
+	#[test]
+	fn determinize_with_mapping() {
+		let mut state_builder = U32StateBuilder::default();
+		let alts = ["ab", "ac"].map(|s| NFA::from_chars(s.chars(), &mut state_builder).unwrap());
+		let nfa = NFA::union_all(alts, &mut state_builder).unwrap();
+
+		let (dfa, subsets) = nfa.determinize_with_mapping();
+		assert_eq!(dfa.states().len(), subsets.len());
+
+		let initial_subset: BTreeSet<u32> = nfa
+			.modulo_epsilon_state(nfa.initial_states())
+			.into_iter()
+			.copied()
+			.collect();
+		assert_eq!(subsets[*dfa.initial_state() as usize], initial_subset);
+
+		for q in dfa.states() {
+			let is_final = subsets[*q as usize].iter().any(|q| nfa.is_final_state(q));
+			assert_eq!(dfa.is_final_state(q), is_final)
+		}
+	}
+
+	#[test]
+	fn determinize_with_mapping_transitions() {
+		let mut state_builder = U32StateBuilder::default();
+		let class = |c: char| RangeSet::from_iter([c]);
+		let alts = [
+			vec![(class('a'), Quantifier::One), (class('b'), Quantifier::One)],
+			vec![
+				(class('a'), Quantifier::One),
+				(RangeSet::from_iter(['a'..='c']), Quantifier::One),
+				(class('b'), Quantifier::One),
+			],
+			vec![
+				(class('b'), Quantifier::One),
+				(class('c'), Quantifier::Star),
+			],
+		]
+		.map(|seq| NFA::from_class_sequence(&seq, &mut state_builder).unwrap());
+		let nfa = NFA::union_all(alts, &mut state_builder).unwrap();
+
+		let (dfa, subsets) = nfa.determinize_with_mapping();
+		for (q, q_transitions) in dfa.transitions() {
+			for (range, r) in q_transitions {
+				let c = range.first().unwrap();
+				let targets = subsets[*q as usize].iter().flat_map(|q| {
+					nfa.successors(q)
+						.filter(|(label, _)| label.as_ref().map_or(false, |l| l.contains(c)))
+						.flat_map(|(_, targets)| targets)
+				});
+				let expected: BTreeSet<u32> = nfa
+					.modulo_epsilon_state(targets)
+					.into_iter()
+					.copied()
+					.collect();
+				assert_eq!(subsets[*r as usize], expected)
+			}
+		}
+	}
+
+	#[test]
+	fn structural_eq() {
+		use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+
+		fn hash(nfa: &NFA) -> u64 {
+			let mut hasher = DefaultHasher::new();
+			nfa.hash(&mut hasher);
+			hasher.finish()
+		}
+
+		let a: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+		let b: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+		let c: NFA = NFA::from_chars("ac".chars(), U32StateBuilder::default()).unwrap();
+
+		assert_eq!(a, b);
+		assert_eq!(hash(&a), hash(&b));
+		assert_ne!(a, c);
+
+		let mut map = HashMap::new();
+		map.insert(a, 1);
+		assert_eq!(map.get(&b), Some(&1));
+		assert_eq!(map.get(&c), None);
+	}
+
+	#[test]
+	fn is_singleton() {
+		let a = || Some(RangeSet::from_iter(['a']));
+		let b = || Some(RangeSet::from_iter(['b']));
+
+		// epsilon-padded `a`, with a dead-end branch.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add(1, a(), 2);
+		nfa.add(1, b(), 4);
+		nfa.add(2, None, 3);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(3);
+		assert!(nfa.is_singleton());
+
+		// the same string along two paths.
+		nfa.add(0, a(), 3);
+		assert!(nfa.is_singleton());
+
+		// `a` or `ab`.
+		nfa.add(3, b(), 5);
+		nfa.add_final_state(5);
+		assert!(!nfa.is_singleton());
+
+		// empty string only.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(1);
+		assert!(nfa.is_singleton());
+
+		// empty language.
+		assert!(!NFA::<u32>::new().is_singleton());
+		let mut nfa: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+		nfa.final_states.clear();
+		assert!(!nfa.is_singleton());
+
+		// two strings.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, a(), 1);
+		nfa.add(0, b(), 1);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(1);
+		assert!(!nfa.is_singleton());
+
+		// cycle on an accepting path.
+		nfa.add(1, None, 0);
+		assert!(!nfa.is_singleton());
+	}
+
+	#[test]
+	fn to_singleton() {
+		let a = || Some(RangeSet::from_iter(['a']));
+		let b = || Some(RangeSet::from_iter(['b']));
+
+		// epsilon-padded `a`, with a dead-end branch.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add(1, a(), 2);
+		nfa.add(1, b(), 4);
+		nfa.add(2, None, 3);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(3);
+		assert_eq!(nfa.to_singleton().as_deref(), Some("a"));
+
+		// the same string along two paths.
+		nfa.add(0, a(), 3);
+		assert_eq!(nfa.to_singleton().as_deref(), Some("a"));
+
+		// `a` or `ab`.
+		nfa.add(3, b(), 5);
+		nfa.add_final_state(5);
+		assert_eq!(nfa.to_singleton(), None);
+
+		// empty string only.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(1);
+		assert_eq!(nfa.to_singleton().as_deref(), Some(""));
+
+		// empty language.
+		assert_eq!(NFA::<u32>::new().to_singleton(), None);
+		let mut nfa: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+		assert_eq!(nfa.to_singleton().as_deref(), Some("ab"));
+		nfa.final_states.clear();
+		assert_eq!(nfa.to_singleton(), None);
+
+		// two strings.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, a(), 1);
+		nfa.add(0, b(), 1);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(1);
+		assert_eq!(nfa.to_singleton(), None);
+
+		// cycle on an accepting path.
+		nfa.add(1, None, 0);
+		assert_eq!(nfa.to_singleton(), None);
+	}
+
+	#[test]
+	fn union_with_builder() {
+		let mut state_builder = U32StateBuilder::default();
+		let mut nfa: NFA = NFA::from_chars("a".chars(), &mut state_builder).unwrap();
+		let other: NFA = NFA::from_chars("b".chars(), U32StateBuilder::default()).unwrap();
+		assert_eq!(nfa.initial_states(), other.initial_states());
+
+		nfa.union_with_builder(other, &mut state_builder).unwrap();
+		assert_eq!(nfa.transitions().count(), 4);
+		assert_eq!(nfa.initial_states().len(), 2);
+		assert!(accepts(&nfa, "a"));
+		assert!(accepts(&nfa, "b"));
+		assert!(!accepts(&nfa, "ab"));
+		assert!(!accepts(&nfa, ""));
+	}
+
+	#[test]
+	fn compile_table() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(['a', 'b'].into_iter().collect(), Quantifier::Star),
+				(['a'..='z'].into_iter().collect(), Quantifier::One),
+				(['0'].into_iter().collect(), Quantifier::Optional),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		let table = nfa.compile_table(usize::MAX).unwrap();
+		let (dfa, subsets) = nfa.determinize_with_mapping();
+		assert_eq!(table.len(), subsets.len());
+		assert_eq!(table.len(), dfa.to_table().len());
+		assert!(nfa.compile_table(subsets.len()).is_ok());
+		assert!(nfa.compile_table(subsets.len() - 1).is_err());
+		assert_eq!(
+			table.transitions_from(0).len(),
+			dfa.transitions_from(&0).count()
+		);
+
+		for input in ["", "a", "ab", "abz", "abz0", "z0", "0", "abz00", "c1"] {
+			assert_eq!(table.is_match(input), accepts(&nfa, input), "{input:?}");
+			assert_eq!(dfa.to_table().is_match(input), accepts(&nfa, input))
+		}
+
+		assert!(!NFA::<u32>::new()
+			.compile_table(usize::MAX)
+			.unwrap()
+			.is_match(""));
+	}
+
+	#[test]
+	fn step() {
+		let nfa: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+
+		let q = nfa.initial_state().unwrap();
+		let q = match nfa.step(q, 'a') {
+			StepResult::Alive(q) => q,
+			StepResult::Dead { .. } => panic!("unexpected rejection"),
+		};
+
+		assert!(matches!(
+			nfa.step(q, 'x'),
+			StepResult::Dead { at_token: 'x' }
+		));
+	}
+
+	#[test]
+	fn from_length_set() {
+		let lengths = [2, 4].into_iter().collect();
+		let nfa: NFA =
+			NFA::from_length_set(&lengths, any_char(), U32StateBuilder::default()).unwrap();
+
+		assert!(accepts(&nfa, "ab"));
+		assert!(accepts(&nfa, "abcd"));
+		assert!(!accepts(&nfa, "abc"));
+		assert!(!accepts(&nfa, ""));
+		assert!(!accepts(&nfa, "abcde"));
+
+		let nfa: NFA =
+			NFA::from_length_set(&BTreeSet::new(), any_char(), U32StateBuilder::default()).unwrap();
+		assert!(!accepts(&nfa, ""));
+		assert!(!accepts(&nfa, "a"));
+	}
+
+	#[test]
+	fn epsilon_wrapping() {
+		let mut state_builder = U32StateBuilder::default();
+		let alts = ["ab", "c", ""].map(|s| NFA::from_chars(s.chars(), &mut state_builder).unwrap());
+		let mut nfa = NFA::new();
+		for alt in alts {
+			nfa.union(alt)
+		}
+		assert_eq!(nfa.initial_states().len(), 3);
+		assert_eq!(nfa.final_states().len(), 3);
+
+		let q = nfa.prepend_epsilon_start(&mut state_builder).unwrap();
+		let r = nfa.append_epsilon_accept(&mut state_builder).unwrap();
+		assert_eq!(nfa.initial_states(), &BTreeSet::from([q]));
+		assert_eq!(nfa.final_states(), &BTreeSet::from([r]));
+
+		for input in ["ab", "c", ""] {
+			assert!(accepts(&nfa, input))
+		}
+
+		for input in ["a", "abc", "cc"] {
+			assert!(!accepts(&nfa, input))
+		}
+	}
+
+	#[test]
+	fn containing() {
+		let pattern: NFA = NFA::from_chars("bc".chars(), U32StateBuilder::default()).unwrap();
+		let nfa = NFA::containing(&pattern, &mut U32StateBuilder::default()).unwrap();
+
+		assert!(accepts(&nfa, "aabccd"));
+		assert!(accepts(&nfa, "bc"));
+		assert!(accepts(&nfa, "abc"));
+		assert!(!accepts(&nfa, "acbd"));
+		assert!(!accepts(&nfa, ""));
+	}
+
+	#[test]
+	fn merge_duplicate_states() {
+		let a = || Some(RangeSet::from_iter(['a']));
+		let b = || Some(RangeSet::from_iter(['b']));
+
+		// `ab|bb`, with two identical states reading the final `b`.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, a(), 1);
+		nfa.add(0, b(), 2);
+		nfa.add(1, b(), 3);
+		nfa.add(2, b(), 3);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(3);
+
+		nfa.merge_duplicate_states();
+		assert_eq!(nfa.transitions().count(), 3);
+		assert_eq!(nfa.num_transitions(), 3);
+
+		for input in ["ab", "bb"] {
+			assert!(accepts(&nfa, input))
+		}
+
+		for input in ["", "a", "b", "ba", "abb"] {
+			assert!(!accepts(&nfa, input))
+		}
+	}
+
+	#[test]
+	fn collapse_epsilon_chains() {
+		let a = || Some(RangeSet::from_iter(['a']));
+
+		// `0 -ε-> 1 -ε-> 2 -a-> 3`, with `4 -a-> 1`.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add(1, None, 2);
+		nfa.add(2, a(), 3);
+		nfa.add(4, a(), 1);
+		nfa.add_initial_state(0);
+		nfa.add_initial_state(4);
+		nfa.add_final_state(3);
+
+		nfa.collapse_epsilon_chains();
+		assert!(nfa.transitions().all(|(q, _)| *q != 1));
+		assert!(nfa.successors(&0).eq([(&None, &BTreeSet::from([2]))]));
+		assert!(nfa.successors(&4).eq([(&a(), &BTreeSet::from([2]))]));
+
+		for input in ["a", "aa"] {
+			assert!(accepts(&nfa, input))
+		}
+		for input in ["", "aaa"] {
+			assert!(!accepts(&nfa, input))
+		}
+
+		// successive states of a chain are collapsed through the edges
+		// redirected by the previous ones.
+		let b = || Some(RangeSet::from_iter(['b']));
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, a(), 1);
+		nfa.add(1, None, 2);
+		nfa.add(2, None, 3);
+		nfa.add(3, None, 4);
+		nfa.add(4, b(), 5);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(5);
+
+		nfa.collapse_epsilon_chains();
+		assert!(nfa.successors(&0).eq([(&a(), &BTreeSet::from([4]))]));
+		assert_eq!(nfa.num_transitions(), 2);
+		assert!(accepts(&nfa, "ab"));
+
+		// initial and final states are kept.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add(1, None, 2);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(1);
+		nfa.add_final_state(2);
+		nfa.collapse_epsilon_chains();
+		assert_eq!(nfa.num_transitions(), 2);
+	}
+
+	#[test]
+	fn merge_duplicate_sub_automata() {
+		let mut state_builder = U32StateBuilder::default();
+		let alts: Vec<NFA> = ["abc", "abc", "xbc"]
+			.iter()
+			.map(|w| NFA::from_chars(w.chars(), &mut state_builder).unwrap())
+			.collect();
+		let mut nfa = NFA::union_all(alts, &mut state_builder).unwrap();
+		assert_eq!(nfa.transitions().count(), 13);
+
+		nfa.merge_duplicate_states();
+
+		// the two `abc` branches are merged, sharing their `bc` suffix with
+		// the `xbc` branch.
+		assert_eq!(nfa.transitions().count(), 6);
+		for input in ["abc", "xbc"] {
+			assert!(accepts(&nfa, input))
+		}
+		for input in ["", "bc", "ab", "abcc"] {
+			assert!(!accepts(&nfa, input))
+		}
+	}
+
+	#[test]
+	fn merge_duplicate_states_tags() {
+		let mut nfa: NFA = NFA::new();
+		nfa.add_initial_state(0);
+		nfa.add(0, Some(RangeSet::from_iter(['a'])), 1);
+		nfa.add(0, Some(RangeSet::from_iter(['b'])), 2);
+		nfa.add_tagged_epsilon(1, 3, Tag(1));
+		nfa.add_tagged_epsilon(2, 3, Tag(2));
+		nfa.add_final_state(3);
+
+		let expected = nfa.match_with_tags("b");
+		assert_eq!(expected, Some(vec![(Tag(2), 1)]));
+
+		nfa.merge_duplicate_states();
+		assert_eq!(nfa.match_with_tags("a"), Some(vec![(Tag(1), 1)]));
+		assert_eq!(nfa.match_with_tags("b"), expected);
+	}
+
+	#[test]
+	fn validate() {
+		let mut nfa: NFA = NFA::from_chars("ab".chars(), U32StateBuilder::default()).unwrap();
+		assert_eq!(nfa.validate(), Ok(()));
+		assert_eq!(nfa.validate_epsilon_acyclic(), Ok(()));
+
+		nfa.add_final_state(42);
+		assert_eq!(nfa.validate(), Err(NfaError::UndeclaredState(42)));
+
+		nfa.add_state(42);
+		assert_eq!(nfa.validate(), Ok(()));
+
+		// epsilon cycle `0 -> 1 -> 2 -> 1`.
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, None, 1);
+		nfa.add(1, None, 2);
+		nfa.add(2, Some(RangeSet::from_iter(['a'])), 0);
+		nfa.add_initial_state(0);
+		assert_eq!(nfa.validate_epsilon_acyclic(), Ok(()));
+
+		nfa.add(2, None, 1);
+		assert_eq!(nfa.validate(), Ok(()));
+		assert!(matches!(
+			nfa.validate_epsilon_acyclic(),
+			Err(NfaError::EpsilonCycle(1 | 2))
+		));
+	}
+
+	#[test]
+	fn restrict_alphabet() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[(any_char(), Quantifier::Plus)],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		assert!(accepts(&nfa, "ab1"));
+
+		let restricted = nfa.restrict_alphabet(&['a'..='z'].into_iter().collect());
+		assert!(accepts(&restricted, "abc"));
+		assert!(!accepts(&restricted, "ab1"));
+		assert!(!accepts(&restricted, "1"));
+
+		let restricted = nfa.restrict_alphabet(&RangeSet::new());
+		assert_eq!(restricted.num_transitions(), 0);
+	}
+
+	/// Reference implementation of [`NFA::product`], intersecting every pair
+	/// of labels.
+	fn naive_product(a: &NFA, b: &NFA) -> NFA<(u32, u32)> {
+		let mut result = NFA::new();
+
+		let mut stack = Vec::new();
+		for qa in &a.initial_states {
+			for qb in &b.initial_states {
+				stack.push((*qa, *qb));
+				result.add_initial_state((*qa, *qb));
+			}
+		}
+
+		let mut visited = HashSet::new();
+		while let Some((qa, qb)) = stack.pop() {
+			if visited.insert((qa, qb)) {
+				if a.is_final_state(&qa) && b.is_final_state(&qb) {
+					result.add_final_state((qa, qb));
+				}
+
+				let transitions = result.transitions.entry((qa, qb)).or_default();
+				for (a_label, a_successors) in a.successors(&qa) {
+					for (b_label, b_successors) in b.successors(&qb) {
+						let label = match (a_label, b_label) {
+							(Some(a_label), Some(b_label)) => {
+								let label = charset_intersection(a_label, b_label);
+								if label.is_empty() {
+									continue;
 								}
+
+								Some(label)
+							}
+							(None, None) => None,
+							_ => continue,
+						};
+
+						let successors = transitions.entry(label).or_default();
+						for sa in a_successors {
+							for sb in b_successors {
+								stack.push((*sa, *sb));
+								successors.insert((*sa, *sb));
 							}
 						}
 					}
@@ -546,45 +3666,307 @@ impl<Q: Ord + Hash> Automaton<char> for NFA<Q> {
 			}
 		}
 
-		// epsilon-closure.
-		while let Some(q) = stack.pop() {
-			if let Some(q_transitions) = self.transitions.get(q) {
-				if let Some(targets) = q_transitions.get(&None) {
-					for r in targets {
-						if next_states.insert(r) {
-							stack.push(r);
-						}
-					}
+		result
+	}
+
+	#[test]
+	fn product() {
+		let class = |ranges: &[(char, char)]| -> RangeSet<char> {
+			ranges.iter().map(|&(a, b)| a..=b).collect()
+		};
+
+		let a: NFA = NFA::from_class_sequence(
+			&[
+				(class(&[('a', 'z'), ('0', '9')]), Quantifier::Star),
+				(class(&[('a', 'f')]), Quantifier::One),
+				(class(&[('\u{0}', '\u{10ffff}')]), Quantifier::Optional),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		let mut b: NFA = NFA::new();
+		b.add(0, Some(class(&[('c', 'x')])), 1);
+		b.add(0, Some(class(&[('a', 'd'), ('5', '7')])), 2);
+		b.add(0, None, 2);
+		b.add(1, Some(class(&[('\u{d000}', '\u{f000}'), ('e', 'e')])), 1);
+		b.add(1, None, 0);
+		b.add(2, Some(class(&[('a', 'b'), ('x', 'z')])), 0);
+		b.add_initial_state(0);
+		b.add_final_state(1);
+		b.add_final_state(2);
+
+		for (a, b) in [(&a, &b), (&b, &a), (&a, &a), (&b, &b)] {
+			assert_eq!(a.product(b, |qa, qb| (*qa, *qb)), naive_product(a, b))
+		}
+	}
+
+	#[test]
+	fn anchored_end() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(RangeSet::from_iter(['a']), Quantifier::Plus),
+				(RangeSet::from_iter(['b']), Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let reversed = nfa.anchored_end();
+
+		for input in ["aab", "xaab", "ab", "aabx", "b", ""] {
+			let backward: String = input.chars().rev().collect();
+			let start = Matcher::new(&reversed)
+				.longest_match_from(&backward, 0)
+				.map(|len| input.len() - len);
+
+			assert_eq!(
+				start.map(|start| start..input.len()),
+				DynMatcher::find_with_mode(&nfa, input, crate::MatchMode::Suffix),
+				"{input:?}"
+			)
+		}
+
+		assert!(accepts(&reversed, "baa"));
+		assert!(!accepts(&reversed, "aab"));
+	}
+
+	#[test]
+	fn label_overlaps() {
+		let labels = |ranges: &[&[(char, char)]]| -> Vec<RangeSet<char>> {
+			ranges
+				.iter()
+				.map(|label| label.iter().map(|&(a, b)| a..=b).collect())
+				.collect()
+		};
+
+		let a = labels(&[
+			&[('a', 'z')],
+			&[('0', '9'), ('A', 'Z')],
+			&[('\u{0}', '\u{10ffff}')],
+			&[('e', 'e'), ('x', 'x')],
+		]);
+		let b = labels(&[
+			&[('c', 'f'), ('5', '5')],
+			&[('\u{d000}', '\u{f000}')],
+			&[('!', '!')],
+			&[('a', 'a'), ('x', 'z'), ('B', 'D')],
+		]);
+
+		let mut expected = BTreeMap::new();
+		for (i, a_label) in a.iter().enumerate() {
+			for (j, b_label) in b.iter().enumerate() {
+				let label = charset_intersection(a_label, b_label);
+				if !label.is_empty() {
+					expected.insert((i, j), label);
 				}
 			}
 		}
 
-		if next_states.is_empty() {
-			None
-		} else {
-			states.clear();
-			eprintln!("next is {} len", next_states.len());
-			Some(VisitingState {
-				states: next_states,
-				next_states: states,
-				stack,
-			})
+		assert_eq!(super::label_overlaps(&a, &b), expected);
+	}
+
+	#[test]
+	fn substitute() {
+		let mut state_builder = U32StateBuilder::default();
+		let foo = NFA::from_chars("foo".chars(), &mut state_builder).unwrap();
+		let bar = NFA::from_chars("bar".chars(), &mut state_builder).unwrap();
+		let placeholder = (
+			*foo.final_states().first().unwrap(),
+			*bar.initial_states().first().unwrap(),
+		);
+
+		// `foo<HERE>bar`.
+		let mut template = NFA::new();
+		template.union(foo);
+		template.union(bar);
+		template.initial_states.remove(&placeholder.1);
+		template.final_states.remove(&placeholder.0);
+		assert!(!accepts(&template, "foobar"));
+
+		let sub = NFA::from_chars("xy".chars(), &mut state_builder).unwrap();
+		template.substitute(placeholder, sub);
+
+		assert!(accepts(&template, "fooxybar"));
+		for input in ["foobar", "foo", "xy", "xybar", "fooxy", "fooxbar"] {
+			assert!(!accepts(&template, input), "{input:?}")
 		}
 	}
 
-	fn is_final_state<'a>(&'a self, VisitingState { states, .. }: &Self::State<'a>) -> bool {
-		for &q in states {
-			if self.final_states.contains(q) {
-				return true;
-			}
+	#[test]
+	fn add_many() {
+		let edges: Vec<_> = ('a'..='z')
+			.zip(1..)
+			.map(|(c, r)| (Some(RangeSet::from_iter([c])), r))
+			.chain([(None, 1), (Some(RangeSet::from_iter(['a'])), 2)])
+			.collect();
+
+		let mut a: NFA = NFA::new();
+		a.add_many(0, edges.clone());
+
+		let mut b: NFA = NFA::new();
+		for (label, r) in edges {
+			b.add(0, label, r)
 		}
 
-		false
+		assert_eq!(a, b);
+		assert_eq!(a.num_transitions(), 28);
+		assert_eq!(a.transitions().count(), 27);
 	}
-}
 
-pub struct VisitingState<'a, Q> {
-	states: HashSet<&'a Q>,
-	next_states: HashSet<&'a Q>,
-	stack: Vec<&'a Q>,
+	#[test]
+	fn add_range_map() {
+		let mut map = RangeMap::new();
+		map.insert('a'..='f', 1);
+		map.insert('g'..='m', 2);
+		map.insert('n'..='z', 3);
+		map.insert('0'..='9', 1);
+
+		let mut nfa: NFA = NFA::new();
+		nfa.add_range_map(0, map);
+
+		let successors: Vec<_> = nfa.successors(&0).collect();
+		assert_eq!(
+			successors,
+			[
+				(
+					&Some(RangeSet::from_iter(['0'..='9', 'a'..='f'])),
+					&BTreeSet::from_iter([1])
+				),
+				(
+					&Some(RangeSet::from_iter(['g'..='m'])),
+					&BTreeSet::from_iter([2])
+				),
+				(
+					&Some(RangeSet::from_iter(['n'..='z'])),
+					&BTreeSet::from_iter([3])
+				),
+			]
+		);
+	}
+
+	#[test]
+	fn match_with_tags() {
+		let mut nfa: NFA = NFA::new();
+		nfa.add(0, Some(RangeSet::from_iter(['a'])), 1);
+		nfa.add_tagged_epsilon(1, 2, Tag(0));
+		nfa.add(2, Some(RangeSet::from_iter(['b'])), 3);
+		nfa.add_tagged_epsilon(3, 4, Tag(1));
+		nfa.add(4, Some(RangeSet::from_iter(['c'])), 5);
+		nfa.add_initial_state(0);
+		nfa.add_final_state(5);
+
+		assert!(accepts(&nfa, "abc"));
+		assert_eq!(
+			nfa.match_with_tags("abc"),
+			Some(vec![(Tag(0), 1), (Tag(1), 2)])
+		);
+		assert_eq!(nfa.match_with_tags("ab"), None);
+	}
+
+	#[test]
+	fn match_first() {
+		let mut state_builder = U32StateBuilder::default();
+		let mut branches = |words: &[&str]| -> NFA {
+			let alts: Vec<NFA> = words
+				.iter()
+				.map(|w| NFA::from_chars(w.chars(), &mut state_builder).unwrap())
+				.collect();
+			NFA::with_priorities(alts, &mut state_builder).unwrap()
+		};
+
+		let nfa = branches(&["a", "ab"]);
+		assert_eq!(nfa.match_first("ab"), Some(1));
+		assert_eq!(
+			nfa.longest_match_len("ab".char_indices().map(|(i, c)| (i + c.len_utf8(), c))),
+			Some(2)
+		);
+
+		let nfa = branches(&["ab", "a"]);
+		assert_eq!(nfa.match_first("ab"), Some(2));
+		assert_eq!(nfa.match_first("ac"), Some(1));
+		assert_eq!(nfa.match_first("b"), None);
+
+		let nfa = branches(&["", "a"]);
+		assert_eq!(nfa.match_first("a"), Some(0));
+	}
+
+	#[test]
+	fn from_class_sequence() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(RangeSet::from_iter(['A'..='Z']), Quantifier::One),
+				(RangeSet::from_iter(['0'..='9']), Quantifier::Plus),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		assert!(accepts(&nfa, "A1"));
+		assert!(accepts(&nfa, "A123"));
+		assert!(!accepts(&nfa, "A"));
+		assert!(!accepts(&nfa, "1A"));
+
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(RangeSet::from_iter(['a']), Quantifier::Optional),
+				(RangeSet::from_iter(['b']), Quantifier::Star),
+				(RangeSet::from_iter(['c']), Quantifier::One),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+
+		assert!(accepts(&nfa, "c"));
+		assert!(accepts(&nfa, "ac"));
+		assert!(accepts(&nfa, "abbc"));
+		assert!(accepts(&nfa, "bc"));
+		assert!(!accepts(&nfa, "aac"));
+		assert!(!accepts(&nfa, "ab"));
+	}
+
+	#[test]
+	fn concat_all() {
+		let mut state_builder = U32StateBuilder::default();
+		let nfa: NFA = NFA::concat_all([], &mut state_builder).unwrap();
+		assert!(accepts(&nfa, ""));
+		assert!(!accepts(&nfa, "a"));
+
+		let a = NFA::from_chars(['a'], &mut state_builder).unwrap();
+		let nfa = NFA::concat_all([a], &mut state_builder).unwrap();
+		assert!(accepts(&nfa, "a"));
+		assert!(!accepts(&nfa, ""));
+
+		let parts =
+			["ab", "", "c"].map(|s| NFA::from_chars(s.chars(), &mut state_builder).unwrap());
+		let nfa = NFA::concat_all(parts, &mut state_builder).unwrap();
+		assert!(accepts(&nfa, "abc"));
+		assert!(!accepts(&nfa, "ab"));
+	}
+
+	#[test]
+	fn union_all() {
+		let mut state_builder = U32StateBuilder::default();
+		let nfa: NFA = NFA::union_all([], &mut state_builder).unwrap();
+		assert!(!accepts(&nfa, ""));
+
+		let a = NFA::from_chars(['a'], &mut state_builder).unwrap();
+		let nfa = NFA::union_all([a], &mut state_builder).unwrap();
+		assert!(accepts(&nfa, "a"));
+		assert!(!accepts(&nfa, ""));
+
+		let alts = ["ab", "", "c"].map(|s| NFA::from_chars(s.chars(), &mut state_builder).unwrap());
+		let nfa = NFA::union_all(alts, &mut state_builder).unwrap();
+		assert!(accepts(&nfa, "ab"));
+		assert!(accepts(&nfa, ""));
+		assert!(accepts(&nfa, "c"));
+		assert!(!accepts(&nfa, "abc"));
+	}
+
+	#[test]
+	fn from_chars() {
+		let nfa: NFA = NFA::from_chars(['x', 'y'], U32StateBuilder::default()).unwrap();
+		assert!(accepts(&nfa, "xy"));
+		assert!(!accepts(&nfa, "x"));
+		assert!(!accepts(&nfa, "xyz"));
+	}
 }
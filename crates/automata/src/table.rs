@@ -0,0 +1,536 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use btree_range_map::AnyRange;
+
+use crate::{
+	dfa::{DetTransitions, DfaError},
+	Automaton, DFA,
+};
+
+/// Deterministic finite automaton stored as a flat transition table.
+///
+/// States are numbered from `0`, the initial state. The transitions leaving
+/// each state are stored contiguously and sorted by label, so that computing
+/// the next state is a binary search. This representation is faster to run
+/// than [`DFA`] but cannot be modified.
+///
+/// See [`NFA::compile_table`](crate::NFA::compile_table) and
+/// [`DFA::to_table`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DfaTable {
+	/// Index of the first transition of each state in `transitions`.
+	offsets: Vec<usize>,
+
+	/// Transitions, as `(first, last, target)` triples.
+	transitions: Vec<(char, char, u32)>,
+
+	/// Whether or not each state is final.
+	final_states: Vec<bool>,
+}
+
+impl Default for DfaTable {
+	fn default() -> Self {
+		Self {
+			offsets: vec![0],
+			transitions: Vec::new(),
+			final_states: Vec::new(),
+		}
+	}
+}
+
+impl DfaTable {
+	pub(crate) fn from_dfa<Q: Ord>(dfa: &DFA<Q>) -> Self {
+		let initial_state = dfa.initial_state();
+		let states: Vec<&Q> = std::iter::once(initial_state)
+			.chain(dfa.all_states().into_iter().filter(|q| *q != initial_state))
+			.collect();
+		let index: BTreeMap<&Q, u32> = states.iter().copied().zip(0..).collect();
+
+		let mut table = Self::default();
+		for q in states {
+			table.push_state(dfa.is_final_state(q));
+
+			for (range, r) in dfa.transitions_from(q) {
+				if let (Some(a), Some(b)) = (range.first(), range.last()) {
+					table.push_transition(a, b, index[r])
+				}
+			}
+		}
+
+		table
+	}
+
+	/// Adds a new state to the table, returning its index.
+	///
+	/// The transitions added next with [`Self::push_transition`] leave this
+	/// state.
+	pub(crate) fn push_state(&mut self, is_final: bool) -> u32 {
+		let q = self.final_states.len() as u32;
+		if q > 0 {
+			self.offsets.push(self.transitions.len());
+		}
+
+		self.final_states.push(is_final);
+		q
+	}
+
+	/// Adds a transition leaving the last pushed state.
+	///
+	/// Transitions must be pushed in increasing label order.
+	pub(crate) fn push_transition(&mut self, first: char, last: char, target: u32) {
+		debug_assert!(self.transitions[*self.offsets.last().unwrap()..]
+			.last()
+			.map_or(true, |(_, b, _)| *b < first));
+		self.transitions.push((first, last, target))
+	}
+
+	/// Returns the number of states.
+	pub fn len(&self) -> usize {
+		self.final_states.len()
+	}
+
+	/// Checks if the table has no state, in which case it recognizes nothing.
+	pub fn is_empty(&self) -> bool {
+		self.final_states.is_empty()
+	}
+
+	/// Checks if the given state is final.
+	pub fn is_final_state(&self, q: u32) -> bool {
+		self.final_states
+			.get(q as usize)
+			.copied()
+			.unwrap_or_default()
+	}
+
+	/// Returns the transitions leaving the given state, as
+	/// `(first, last, target)` triples sorted by label.
+	pub fn transitions_from(&self, q: u32) -> &[(char, char, u32)] {
+		let q = q as usize;
+		let start = self.offsets[q];
+		let end = self
+			.offsets
+			.get(q + 1)
+			.copied()
+			.unwrap_or(self.transitions.len());
+		&self.transitions[start..end]
+	}
+
+	/// Returns the state reached from `q` by reading `c`, if any.
+	pub fn next_state(&self, q: u32, c: char) -> Option<u32> {
+		let transitions = self.transitions_from(q);
+		let i = transitions.partition_point(|(_, b, _)| *b < c);
+		match transitions.get(i) {
+			Some((a, _, r)) if *a <= c => Some(*r),
+			_ => None,
+		}
+	}
+
+	/// Checks if the table recognizes the given input.
+	pub fn is_match(&self, input: &str) -> bool {
+		if self.is_empty() {
+			return false;
+		}
+
+		let mut q = 0;
+
+		for c in input.chars() {
+			match self.next_state(q, c) {
+				Some(r) => q = r,
+				None => return false,
+			}
+		}
+
+		self.is_final_state(q)
+	}
+}
+
+impl Automaton<char> for DfaTable {
+	type State<'a> = u32;
+
+	fn initial_state(&self) -> Option<u32> {
+		if self.is_empty() {
+			None
+		} else {
+			Some(0)
+		}
+	}
+
+	fn next_state(&self, q: u32, token: char) -> Option<u32> {
+		DfaTable::next_state(self, q, token)
+	}
+
+	fn is_final_state(&self, q: &u32) -> bool {
+		DfaTable::is_final_state(self, *q)
+	}
+}
+
+/// Value of a missing transition in a [`DenseDfa`] table.
+const DEAD: u32 = u32::MAX;
+
+/// Deterministic finite automaton stored as a dense transition table.
+///
+/// Characters are grouped into classes of characters on which every state
+/// behaves the same. The table stores the target of every state for every
+/// class, so that computing the next state only requires looking up the
+/// class of the character. The class of ASCII characters is precomputed.
+///
+/// See [`DFA::compile_dense`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DenseDfa {
+	/// Sorted class boundaries: the class of `c` is the number of boundaries
+	/// lower or equal to `c`.
+	boundaries: Vec<u32>,
+
+	/// Class of each ASCII character.
+	ascii_classes: Vec<u32>,
+
+	/// Number of character classes.
+	num_classes: usize,
+
+	/// Transition table, indexed by `state * num_classes + class`.
+	table: Vec<u32>,
+
+	initial_state: u32,
+
+	final_states: Vec<bool>,
+}
+
+impl DenseDfa {
+	pub(crate) fn from_dfa<Q: Ord>(dfa: &DFA<Q>) -> Self {
+		let index: BTreeMap<&Q, u32> = dfa.all_states().into_iter().zip(0..).collect();
+
+		let mut boundaries = BTreeSet::new();
+		for range in dfa.transitions().values().flat_map(BTreeMap::keys) {
+			if let (Some(a), Some(b)) = (range.first(), range.last()) {
+				boundaries.insert(a as u32);
+				boundaries.insert(b as u32 + 1);
+			}
+		}
+
+		let boundaries: Vec<u32> = boundaries.into_iter().collect();
+		let num_classes = boundaries.len() + 1;
+		let class_of = |c: u32| boundaries.partition_point(|b| *b <= c) as u32;
+
+		let mut table = vec![DEAD; index.len() * num_classes];
+		let mut final_states = vec![false; index.len()];
+		for (q, &i) in &index {
+			final_states[i as usize] = dfa.is_final_state(q);
+
+			for (range, r) in dfa.transitions_from(q) {
+				if let (Some(a), Some(b)) = (range.first(), range.last()) {
+					for class in class_of(a as u32)..=class_of(b as u32) {
+						table[i as usize * num_classes + class as usize] = index[r]
+					}
+				}
+			}
+		}
+
+		Self {
+			ascii_classes: (0..0x80).map(class_of).collect(),
+			num_classes,
+			table,
+			initial_state: index[dfa.initial_state()],
+			final_states,
+			boundaries,
+		}
+	}
+
+	/// Returns the number of states.
+	pub fn len(&self) -> usize {
+		self.final_states.len()
+	}
+
+	/// Checks if the automaton has no state.
+	///
+	/// This is never the case, since the automaton has an initial state.
+	pub fn is_empty(&self) -> bool {
+		self.final_states.is_empty()
+	}
+
+	/// Returns the number of character classes.
+	pub fn num_classes(&self) -> usize {
+		self.num_classes
+	}
+
+	/// Returns the class of the given character.
+	fn class_of(&self, c: char) -> usize {
+		if c.is_ascii() {
+			self.ascii_classes[c as usize] as usize
+		} else {
+			self.boundaries.partition_point(|b| *b <= c as u32)
+		}
+	}
+
+	/// Checks if the given state is final.
+	pub fn is_final_state(&self, q: u32) -> bool {
+		self.final_states[q as usize]
+	}
+
+	/// Returns the state reached from `q` by reading `c`, if any.
+	pub fn next_state(&self, q: u32, c: char) -> Option<u32> {
+		match self.table[q as usize * self.num_classes + self.class_of(c)] {
+			DEAD => None,
+			r => Some(r),
+		}
+	}
+
+	/// Checks if the automaton recognizes the given input.
+	///
+	/// Reading stops as soon as a character has no transition.
+	pub fn accepts(&self, input: &str) -> bool {
+		let mut q = self.initial_state;
+
+		for c in input.chars() {
+			match self.next_state(q, c) {
+				Some(r) => q = r,
+				None => return false,
+			}
+		}
+
+		self.is_final_state(q)
+	}
+
+	/// Checks if the automaton recognizes the given input.
+	///
+	/// This is the same as [`Self::accepts`], consistent with
+	/// [`DfaTable::is_match`] and [`DynMatcher::is_match`]. To search for a
+	/// match inside the input, use [`DynMatcher::find`].
+	///
+	/// [`DynMatcher::is_match`]: crate::DynMatcher::is_match
+	/// [`DynMatcher::find`]: crate::DynMatcher::find
+	pub fn is_match(&self, input: &str) -> bool {
+		self.accepts(input)
+	}
+}
+
+impl Automaton<char> for DenseDfa {
+	type State<'a> = u32;
+
+	fn initial_state(&self) -> Option<u32> {
+		Some(self.initial_state)
+	}
+
+	fn next_state(&self, q: u32, token: char) -> Option<u32> {
+		DenseDfa::next_state(self, q, token)
+	}
+
+	fn is_final_state(&self, q: &u32) -> bool {
+		DenseDfa::is_final_state(self, *q)
+	}
+}
+
+/// Deterministic finite automaton made only of primitive values.
+///
+/// This representation is meant to be copied as is across an FFI boundary,
+/// or embedded in a non-Rust runtime. States are numbered from `0` to
+/// `num_states` (excluded), and characters are represented by their code
+/// point. A range of characters never contains a surrogate code point: a
+/// range spanning the surrogate gap is represented by two transitions.
+///
+/// See [`DFA::to_flat`] and [`DFA::from_flat`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct FlatDfa {
+	/// Number of states.
+	pub num_states: u32,
+
+	/// Initial state.
+	pub initial: u32,
+
+	/// Final states.
+	pub finals: Vec<u32>,
+
+	/// Transitions, as `(source, first, last, target)` tuples where `first`
+	/// and `last` are the (inclusive) bounds of the label.
+	pub transitions: Vec<(u32, u32, u32, u32)>,
+}
+
+/// Invalid [`FlatDfa`] error, returned by [`DFA::from_flat`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FlatDfaError {
+	/// A state is not lower than the number of states.
+	#[error("invalid state `{0}`")]
+	InvalidState(u32),
+
+	/// A range bound is not a character, or the range is empty.
+	#[error("invalid character range `{0:#x}-{1:#x}`")]
+	InvalidRange(u32, u32),
+
+	/// Two transitions leaving the same state have overlapping labels.
+	#[error(transparent)]
+	NonDeterministic(#[from] DfaError<u32>),
+}
+
+impl FlatDfa {
+	pub(crate) fn from_dfa<Q: Ord>(dfa: &DFA<Q>) -> Self {
+		let index: BTreeMap<&Q, u32> = dfa.all_states().into_iter().zip(0..).collect();
+
+		let mut transitions = Vec::new();
+		for (q, &i) in &index {
+			for (range, r) in dfa.transitions_from(q) {
+				if let (Some(a), Some(b)) = (range.first(), range.last()) {
+					let (a, b) = (a as u32, b as u32);
+					if a < 0xd800 && b > 0xdfff {
+						transitions.push((i, a, 0xd7ff, index[r]));
+						transitions.push((i, 0xe000, b, index[r]));
+					} else {
+						transitions.push((i, a, b, index[r]))
+					}
+				}
+			}
+		}
+
+		Self {
+			num_states: index.len() as u32,
+			initial: index[dfa.initial_state()],
+			finals: dfa.final_states().iter().map(|q| index[q]).collect(),
+			transitions,
+		}
+	}
+
+	pub(crate) fn to_dfa(&self) -> Result<DFA<u32>, FlatDfaError> {
+		let state = |q: u32| {
+			if q < self.num_states {
+				Ok(q)
+			} else {
+				Err(FlatDfaError::InvalidState(q))
+			}
+		};
+
+		let mut transitions = DetTransitions::new();
+		for &(q, a, b, r) in &self.transitions {
+			let range: AnyRange<char> = match (char::from_u32(a), char::from_u32(b)) {
+				(Some(first), Some(last)) if first <= last => (first..=last).into(),
+				_ => return Err(FlatDfaError::InvalidRange(a, b)),
+			};
+
+			let (q, r) = (state(q)?, state(r)?);
+			if transitions
+				.insert(q, range, r)
+				.map_or(false, |other| other != r)
+			{
+				return Err(DfaError::Overlap(q, range, range).into());
+			}
+		}
+
+		let final_states = self
+			.finals
+			.iter()
+			.map(|&q| state(q))
+			.collect::<Result<_, _>>()?;
+		let mut dfa = DFA::try_from_parts(state(self.initial)?, final_states, transitions)?;
+
+		for q in 0..self.num_states {
+			dfa.declare_state(q)
+		}
+
+		Ok(dfa)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		nfa::{Quantifier, U32StateBuilder},
+		DynMatcher, NFA,
+	};
+
+	#[test]
+	fn flat_dfa() {
+		let mut dfa: DFA<char> = DFA::new('i');
+		dfa.add('i', ('a'..='\u{10ffff}').into(), 'f');
+		dfa.add('f', ('0'..='9').into(), 'f');
+		dfa.add('f', ('\u{e000}'..='\u{e000}').into(), 'x');
+		dfa.add_final_state('f');
+
+		let flat = dfa.to_flat();
+		assert_eq!(flat.num_states, 3);
+		assert_eq!(flat.initial, 1);
+		assert_eq!(flat.finals, [0]);
+		assert_eq!(
+			flat.transitions,
+			[
+				(0, 0x30, 0x39, 0),
+				(0, 0xe000, 0xe000, 2),
+				(1, 0x61, 0xd7ff, 0),
+				(1, 0xe000, 0x10ffff, 0),
+			]
+		);
+
+		let round_trip = DFA::from_flat(&flat).unwrap();
+		assert_eq!(round_trip.to_flat(), flat);
+		for input in ["a", "\u{10ffff}12", "\u{e000}\u{e000}", "é5"] {
+			assert_eq!(round_trip.accepts(input), dfa.accepts(input), "{input:?}");
+		}
+
+		let mut invalid = flat.clone();
+		invalid.transitions.push((1, 0xd800, 0xd800, 0));
+		assert_eq!(
+			DFA::from_flat(&invalid),
+			Err(FlatDfaError::InvalidRange(0xd800, 0xd800))
+		);
+
+		let mut invalid = flat.clone();
+		invalid.finals.push(3);
+		assert_eq!(DFA::from_flat(&invalid), Err(FlatDfaError::InvalidState(3)));
+
+		let mut invalid = flat.clone();
+		invalid.transitions.push((1, 0x7a, 0x7a, 2));
+		assert!(matches!(
+			DFA::from_flat(&invalid),
+			Err(FlatDfaError::NonDeterministic(_))
+		));
+
+		// the same label with two different targets.
+		let mut invalid = flat;
+		invalid.transitions.push((2, 0x61, 0x61, 0));
+		invalid.transitions.push((2, 0x61, 0x61, 1));
+		assert!(matches!(
+			DFA::from_flat(&invalid),
+			Err(FlatDfaError::NonDeterministic(_))
+		));
+	}
+
+	#[test]
+	fn dense_dfa() {
+		let nfa: NFA = NFA::from_class_sequence(
+			&[
+				(
+					['a'..='z', 'é'..='é'].into_iter().collect(),
+					Quantifier::Plus,
+				),
+				(['0'..='9'].into_iter().collect(), Quantifier::Star),
+				(
+					['\u{10000}'..='\u{10ffff}'].into_iter().collect(),
+					Quantifier::Optional,
+				),
+			],
+			U32StateBuilder::default(),
+		)
+		.unwrap();
+		let dfa = nfa.determinize(|q| q.iter().copied().copied().collect::<Vec<_>>());
+		let dense = dfa.compile_dense();
+
+		assert_eq!(dense.len(), dfa.all_states().len());
+
+		for input in [
+			"",
+			"a",
+			"abc",
+			"é",
+			"aé12",
+			"a1b",
+			"1",
+			"z9\u{10000}",
+			"z\u{10ffff}",
+			"A",
+			"a\u{ffff}",
+			"ab\u{10000}\u{10000}",
+		] {
+			assert_eq!(dense.accepts(input), dfa.accepts(input), "{input:?}");
+			assert_eq!(dense.is_match(input), dfa.accepts(input), "{input:?}");
+		}
+
+		assert_eq!(DynMatcher::find(&dense, "--ab1-"), Some(2..5));
+	}
+}
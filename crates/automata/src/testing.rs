@@ -0,0 +1,75 @@
+//! Test utilities.
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{DFA, NFA};
+
+/// Determinizes the given automaton with integer states.
+fn determinize(nfa: &NFA<u32>) -> DFA<usize> {
+	let mut ids: HashMap<BTreeSet<&u32>, usize> = HashMap::new();
+	nfa.determinize(|q| {
+		let len = ids.len();
+		*ids.entry(q.clone()).or_insert(len)
+	})
+}
+
+/// Asserts that the given automata recognize the same language.
+///
+/// Both automata are determinized and their symmetric difference is
+/// computed. If it is not empty, this function panics with a shortest string
+/// recognized by exactly one of the automata.
+#[track_caller]
+pub fn assert_language_eq(a: &NFA<u32>, b: &NFA<u32>) {
+	let a = determinize(a);
+	let b = determinize(b);
+
+	if let Some(witness) = a.symmetric_difference(&b).shortest_word() {
+		let (recognizing, rejecting) = if a.accepts(&witness) {
+			("left", "right")
+		} else {
+			("right", "left")
+		};
+
+		panic!(
+			"languages differ: {witness:?} is recognized by the {recognizing} automaton but not by the {rejecting} one"
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::nfa::{Quantifier, U32StateBuilder};
+
+	#[test]
+	fn equal_languages() {
+		let mut state_builder = U32StateBuilder::default();
+
+		// `a+` and `aa*`.
+		let a = NFA::from_class_sequence(
+			&[(['a'].into_iter().collect(), Quantifier::Plus)],
+			&mut state_builder,
+		)
+		.unwrap();
+		let b = NFA::from_class_sequence(
+			&[
+				(['a'].into_iter().collect(), Quantifier::One),
+				(['a'].into_iter().collect(), Quantifier::Star),
+			],
+			&mut state_builder,
+		)
+		.unwrap();
+
+		assert_language_eq(&a, &b)
+	}
+
+	#[test]
+	#[should_panic(expected = "\"ab\" is recognized by the right automaton")]
+	fn different_languages() {
+		let mut state_builder = U32StateBuilder::default();
+		let a = NFA::from_chars("abc".chars(), &mut state_builder).unwrap();
+		let mut b = NFA::from_chars("abc".chars(), &mut state_builder).unwrap();
+		b.union(NFA::from_chars("ab".chars(), &mut state_builder).unwrap());
+
+		assert_language_eq(&a, &b)
+	}
+}
@@ -0,0 +1,79 @@
+//! Ordered thread simulation of nondeterministic automata.
+//!
+//! A thread is a state of the automaton carrying a payload, such as the tags
+//! or outputs collected along its path. Threads are kept in priority order:
+//! when two threads reach the same state, only the first one is kept. This is
+//! the core of leftmost-first (Pike VM style) matching, shared by
+//! [`NFA`](crate::NFA) and [`Transducer`](crate::Transducer).
+use std::{collections::BTreeSet, convert::Infallible};
+
+/// Thread of a simulation: a state along with its payload.
+pub(crate) type Thread<'a, Q, P> = (&'a Q, P);
+
+/// Follows the epsilon-transitions from the given threads, in priority order.
+///
+/// The `epsilon_successors` function returns the threads reached from a
+/// thread through a single epsilon-transition, in priority order. It is
+/// called at most once per state, and can abort the closure by returning an
+/// error. A state already reached by a higher-priority thread is not visited
+/// again.
+pub(crate) fn try_epsilon_closure<'a, Q, P, I, E>(
+	threads: Vec<Thread<'a, Q, P>>,
+	mut epsilon_successors: impl FnMut(&'a Q, &P) -> Result<I, E>,
+) -> Result<Vec<Thread<'a, Q, P>>, E>
+where
+	Q: Ord,
+	I: IntoIterator<Item = Thread<'a, Q, P>>,
+	I::IntoIter: DoubleEndedIterator,
+{
+	let mut visited = BTreeSet::new();
+	let mut result = Vec::new();
+
+	let mut stack = threads;
+	stack.reverse();
+
+	while let Some((q, payload)) = stack.pop() {
+		if visited.insert(q) {
+			stack.extend(epsilon_successors(q, &payload)?.into_iter().rev());
+			result.push((q, payload))
+		}
+	}
+
+	Ok(result)
+}
+
+/// Infallible version of [`try_epsilon_closure`].
+pub(crate) fn epsilon_closure<'a, Q, P, I>(
+	threads: Vec<Thread<'a, Q, P>>,
+	mut epsilon_successors: impl FnMut(&'a Q, &P) -> I,
+) -> Vec<Thread<'a, Q, P>>
+where
+	Q: Ord,
+	I: IntoIterator<Item = Thread<'a, Q, P>>,
+	I::IntoIter: DoubleEndedIterator,
+{
+	match try_epsilon_closure(threads, |q, payload| {
+		Ok::<_, Infallible>(epsilon_successors(q, payload))
+	}) {
+		Ok(result) => result,
+		Err(e) => match e {},
+	}
+}
+
+/// Advances every thread by reading one character, in priority order.
+///
+/// The `successors` function returns the threads reached from a thread by
+/// reading the character. The epsilon-closure of the result must then be
+/// computed by the caller.
+pub(crate) fn step<'a, Q, P, I>(
+	threads: Vec<Thread<'a, Q, P>>,
+	mut successors: impl FnMut(&'a Q, &P) -> I,
+) -> Vec<Thread<'a, Q, P>>
+where
+	I: IntoIterator<Item = Thread<'a, Q, P>>,
+{
+	threads
+		.iter()
+		.flat_map(|(q, payload)| successors(q, payload))
+		.collect()
+}
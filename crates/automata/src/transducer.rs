@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use btree_range_map::RangeSet;
+
+use crate::threads::{self, Thread};
+
+/// Transducer transition, with its optional label, target state and emitted
+/// outputs.
+type Transition<Q, O> = (Option<RangeSet<char>>, Q, Vec<O>);
+
+/// Nondeterministic finite-state transducer.
+///
+/// A transducer is an automaton whose transitions emit outputs. A successful
+/// run over an input string produces the concatenation of the outputs of
+/// each traversed transition.
+#[derive(Debug)]
+pub struct Transducer<Q = u32, O = char> {
+	transitions: BTreeMap<Q, Vec<Transition<Q, O>>>,
+	initial_states: BTreeSet<Q>,
+	final_states: BTreeSet<Q>,
+}
+
+impl<Q, O> Default for Transducer<Q, O> {
+	fn default() -> Self {
+		Self {
+			transitions: BTreeMap::new(),
+			initial_states: BTreeSet::new(),
+			final_states: BTreeSet::new(),
+		}
+	}
+}
+
+impl<Q, O> Transducer<Q, O> {
+	/// Create a new empty transducer.
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl<Q: Ord, O> Transducer<Q, O> {
+	/// Adds the given transition to the transducer, emitting `output` when
+	/// traversed.
+	///
+	/// A `None` label denotes an epsilon-transition.
+	pub fn add(&mut self, source: Q, label: Option<RangeSet<char>>, target: Q, output: Vec<O>) {
+		self.transitions
+			.entry(source)
+			.or_default()
+			.push((label, target, output))
+	}
+
+	/// Sets the given state as an initial state.
+	pub fn add_initial_state(&mut self, q: Q) -> bool {
+		self.initial_states.insert(q)
+	}
+
+	/// Checks if the given state is a final state.
+	pub fn is_final_state(&self, q: &Q) -> bool {
+		self.final_states.contains(q)
+	}
+
+	/// Adds a final state to the transducer.
+	pub fn add_final_state(&mut self, q: Q) -> bool {
+		self.final_states.insert(q)
+	}
+
+	/// Follows the epsilon-transitions from the given threads, accumulating
+	/// their outputs.
+	///
+	/// A state already reached by a previous thread is not visited again.
+	fn epsilon_closure<'a>(
+		&'a self,
+		threads: Vec<Thread<'a, Q, Vec<O>>>,
+	) -> Vec<Thread<'a, Q, Vec<O>>>
+	where
+		O: Clone,
+	{
+		threads::epsilon_closure(threads, |q, output| self.successors(q, None, output))
+	}
+
+	/// Returns the threads reached from `q` through the transitions labeled
+	/// by `c`, or the epsilon-transitions if `c` is `None`, in insertion
+	/// order.
+	fn successors<'a>(&'a self, q: &Q, c: Option<char>, output: &[O]) -> Vec<Thread<'a, Q, Vec<O>>>
+	where
+		O: Clone,
+	{
+		self.transitions
+			.get(q)
+			.into_iter()
+			.flatten()
+			.filter(|(label, _, _)| match (label, c) {
+				(Some(label), Some(c)) => label.contains(c),
+				(None, None) => true,
+				_ => false,
+			})
+			.map(|(_, r, r_output)| {
+				let mut output = output.to_vec();
+				output.extend(r_output.iter().cloned());
+				(r, output)
+			})
+			.collect()
+	}
+
+	/// Runs the transducer on the given input, returning the output emitted
+	/// along an accepting path.
+	///
+	/// If more than one path accepts the input, the first one in transition
+	/// insertion order is selected. Returns `None` if the input is not
+	/// accepted.
+	pub fn transduce(&self, input: &str) -> Option<Vec<O>>
+	where
+		O: Clone,
+	{
+		let mut threads = self.epsilon_closure(
+			self.initial_states
+				.iter()
+				.map(|q| (q, Vec::new()))
+				.collect(),
+		);
+
+		for c in input.chars() {
+			let next_threads =
+				threads::step(threads, |q, output| self.successors(q, Some(c), output));
+			threads = self.epsilon_closure(next_threads);
+		}
+
+		threads
+			.into_iter()
+			.find(|(q, _)| self.is_final_state(q))
+			.map(|(_, output)| output)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn transduce() {
+		let mut transducer: Transducer = Transducer::new();
+		transducer.add(0, Some(RangeSet::from_iter(['c'])), 1, vec!['f', 'e', 'l']);
+		transducer.add(1, Some(RangeSet::from_iter(['a'])), 2, vec!['i', 'n']);
+		transducer.add(2, Some(RangeSet::from_iter(['t'])), 3, vec!['e']);
+		transducer.add_initial_state(0);
+		transducer.add_final_state(3);
+
+		let output: Option<String> = transducer
+			.transduce("cat")
+			.map(|output| output.into_iter().collect());
+		assert_eq!(output.as_deref(), Some("feline"));
+		assert_eq!(transducer.transduce("ca"), None);
+		assert_eq!(transducer.transduce("dog"), None);
+	}
+}
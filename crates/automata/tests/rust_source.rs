@@ -0,0 +1,68 @@
+//! Checks that the code generated by [`DFA::to_rust_source`] compiles without
+//! warnings and recognizes the same language as the automaton.
+//!
+//! The generated functions are checked in under `tests/rust_source`, and
+//! each test makes sure they are still up to date.
+use iregex_automata::{
+	nfa::{Quantifier, U32StateBuilder},
+	DFA, NFA,
+};
+
+mod generated {
+	#![deny(warnings)]
+
+	include!("rust_source/is_abc_quote.rs");
+	include!("rust_source/is_empty_word.rs");
+	include!("rust_source/is_never.rs");
+}
+
+const SAMPLES: [&str; 10] = [
+	"", "a", "'", "a'", "abc'", "cba'", "abc", "abc''", "d'", "a'a",
+];
+
+fn check(dfa: &DFA<u32>, fn_name: &str, source: &str, f: fn(&str) -> bool) {
+	assert_eq!(dfa.to_rust_source(fn_name), source);
+
+	for input in SAMPLES {
+		assert_eq!(f(input), dfa.accepts(input), "input: {input:?}")
+	}
+}
+
+#[test]
+fn abc_quote() {
+	let nfa = NFA::from_class_sequence(
+		&[
+			(['a'..='c'].into_iter().collect(), Quantifier::Plus),
+			(['\''].into_iter().collect(), Quantifier::One),
+		],
+		U32StateBuilder::default(),
+	)
+	.unwrap();
+	let (dfa, _) = nfa.determinize_with_mapping();
+
+	check(
+		&dfa,
+		"is_abc_quote",
+		include_str!("rust_source/is_abc_quote.rs"),
+		generated::is_abc_quote,
+	)
+}
+
+#[test]
+fn no_transitions() {
+	let mut dfa = DFA::new(0);
+	check(
+		&dfa,
+		"is_never",
+		include_str!("rust_source/is_never.rs"),
+		generated::is_never,
+	);
+
+	dfa.add_final_state(0);
+	check(
+		&dfa,
+		"is_empty_word",
+		include_str!("rust_source/is_empty_word.rs"),
+		generated::is_empty_word,
+	)
+}
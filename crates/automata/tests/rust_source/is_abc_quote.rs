@@ -0,0 +1,13 @@
+pub fn is_abc_quote(input: &str) -> bool {
+	const FINAL_STATES: [bool; 3] = [false, false, true];
+	let mut state: usize = 0;
+	for c in input.chars() {
+		state = match (state, c) {
+			(0, 'a'..='c') => 1,
+			(1, '\''..='\'') => 2,
+			(1, 'a'..='c') => 1,
+			_ => return false,
+		};
+	}
+	FINAL_STATES[state]
+}
@@ -0,0 +1,3 @@
+pub fn is_empty_word(input: &str) -> bool {
+	input.is_empty()
+}
@@ -0,0 +1,4 @@
+pub fn is_never(input: &str) -> bool {
+	let _ = input;
+	false
+}
@@ -1,25 +1,97 @@
-use std::{ops::Range, str::Chars};
+use std::{hash::Hash, ops::Range, str::Chars};
 
-use iregex_automata::{Automaton, NFA};
+use iregex_automata::{nfa::VisitingState, Automaton, Literal, NFA};
 
 use crate::Token;
 
+/// Automaton compiled from a part of a regular expression.
+///
+/// Parts recognizing a single constant string are compiled into a
+/// [`Literal`], cheaper to run than an [`NFA`].
+#[derive(Debug)]
+pub enum CompiledAutomaton<Q = u32> {
+	Literal(Literal),
+	Nfa(NFA<Q>),
+}
+
+impl<Q: Ord> CompiledAutomaton<Q> {
+	/// Picks a [`Literal`] automaton if `nfa` recognizes a single string, or
+	/// keeps `nfa` otherwise.
+	pub fn new(nfa: NFA<Q>) -> Self {
+		match Literal::from_nfa(&nfa) {
+			Some(literal) => Self::Literal(literal),
+			None => Self::Nfa(nfa),
+		}
+	}
+}
+
+/// State of a [`CompiledAutomaton`].
+pub enum CompiledAutomatonState<'a, Q> {
+	Literal(usize),
+	Nfa(VisitingState<'a, Q>),
+}
+
+impl<Q: Ord + Hash> Automaton<char> for CompiledAutomaton<Q> {
+	type State<'a>
+		= CompiledAutomatonState<'a, Q>
+	where
+		Self: 'a;
+
+	fn initial_state(&self) -> Option<Self::State<'_>> {
+		match self {
+			Self::Literal(literal) => literal.initial_state().map(CompiledAutomatonState::Literal),
+			Self::Nfa(nfa) => nfa.initial_state().map(CompiledAutomatonState::Nfa),
+		}
+	}
+
+	fn next_state<'a>(&'a self, state: Self::State<'a>, token: char) -> Option<Self::State<'a>> {
+		match (self, state) {
+			(Self::Literal(literal), CompiledAutomatonState::Literal(q)) => literal
+				.next_state(q, token)
+				.map(CompiledAutomatonState::Literal),
+			(Self::Nfa(nfa), CompiledAutomatonState::Nfa(q)) => {
+				nfa.next_state(q, token).map(CompiledAutomatonState::Nfa)
+			}
+			_ => None,
+		}
+	}
+
+	fn is_final_state<'a>(&'a self, state: &Self::State<'a>) -> bool {
+		match (self, state) {
+			(Self::Literal(literal), CompiledAutomatonState::Literal(q)) => {
+				literal.is_final_state(q)
+			}
+			(Self::Nfa(nfa), CompiledAutomatonState::Nfa(q)) => Automaton::is_final_state(nfa, q),
+			_ => false,
+		}
+	}
+
+	fn reset_state<'a>(&'a self, state: Self::State<'a>) -> Option<Self::State<'a>> {
+		match (self, state) {
+			(Self::Nfa(nfa), CompiledAutomatonState::Nfa(q)) => {
+				nfa.reset_state(q).map(CompiledAutomatonState::Nfa)
+			}
+			_ => self.initial_state(),
+		}
+	}
+}
+
 /// Compiled Regular Expression.
-pub struct CompiledRegEx<A = NFA<u32>> {
+pub struct CompiledRegEx<A = CompiledAutomaton<u32>> {
 	pub root: A,
 	pub prefix: A,
 	pub suffix: A,
 }
 
 impl<A> CompiledRegEx<A> {
-	pub fn matches_str<'a>(&self, haystack: &'a str) -> Matches<A, Chars<'a>>
+	pub fn matches_str<'a>(&self, haystack: &'a str) -> Matches<'_, A, Chars<'a>>
 	where
 		A: Automaton<char>,
 	{
 		self.matches(haystack.chars())
 	}
 
-	pub fn matches<H>(&self, haystack: H) -> Matches<A, H>
+	pub fn matches<H>(&self, haystack: H) -> Matches<'_, A, H>
 	where
 		H: Clone + Iterator,
 		A: Automaton<H::Item>,
@@ -52,10 +124,8 @@ where
 		let mut candidate = None;
 
 		loop {
-			if self.regex.root.is_final_state(&root_state) {
-				if self.check_suffix(haystack.clone()) {
-					candidate = Some(end)
-				}
+			if self.regex.root.is_final_state(&root_state) && self.check_suffix(haystack.clone()) {
+				candidate = Some(end)
 			}
 
 			match haystack.next() {
@@ -23,7 +23,7 @@ impl Concatenation {
 
 impl From<Atom> for Concatenation {
 	fn from(value: Atom) -> Self {
-		Self(vec![value.into()])
+		Self(vec![value])
 	}
 }
 
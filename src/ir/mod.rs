@@ -11,7 +11,7 @@ use iregex_automata::{
 	NFA,
 };
 
-use crate::CompiledRegEx;
+use crate::{CompiledAutomaton, CompiledRegEx};
 
 /// Intermediate Regular Expression.
 pub struct IRegEx {
@@ -38,15 +38,22 @@ impl IRegEx {
 	}
 
 	/// Compiles the regular expression.
-	pub fn compile<Q, S>(&self, mut state_builder: S) -> Result<CompiledRegEx<NFA<Q>>, S::Error>
+	///
+	/// Parts of the expression recognizing a single constant string, such
+	/// as anchors, are compiled into a [`Literal`](iregex_automata::Literal)
+	/// automaton instead of an [`NFA`].
+	pub fn compile<Q, S>(
+		&self,
+		mut state_builder: S,
+	) -> Result<CompiledRegEx<CompiledAutomaton<Q>>, S::Error>
 	where
 		Q: Copy + Ord,
 		S: StateBuilder<Q>,
 	{
 		Ok(CompiledRegEx {
-			root: self.root.build_nfa(&mut state_builder)?,
-			prefix: self.prefix.build_nfa(&mut state_builder)?,
-			suffix: self.suffix.build_nfa(&mut state_builder)?,
+			root: CompiledAutomaton::new(self.root.build_nfa(&mut state_builder)?),
+			prefix: CompiledAutomaton::new(self.prefix.build_nfa(&mut state_builder)?),
+			suffix: CompiledAutomaton::new(self.suffix.build_nfa(&mut state_builder)?),
 		})
 	}
 }
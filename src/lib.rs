@@ -15,6 +15,7 @@ pub use ir::*;
 mod compiled;
 pub use compiled::*;
 
+#[allow(clippy::len_without_is_empty)]
 pub trait Token {
 	/// Returns the (byte) length of the token.
 	fn len(&self) -> usize;
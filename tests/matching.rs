@@ -1,6 +1,6 @@
 use std::ops::Range;
 
-use iregex::{Alternation, Atom, Concatenation, IRegEx};
+use iregex::{Alternation, Atom, CompiledAutomaton, Concatenation, IRegEx};
 use iregex_automata::{any_char, nfa::U32StateBuilder, RangeSet};
 
 #[test]
@@ -42,7 +42,7 @@ fn single_match_unanchored() {
 	let vectors = [
 		(Atom::Token(b.clone()).into(), "aba", 1..2),
 		(
-			[Atom::Token(b.clone()).into(), Atom::Token(b).into()]
+			[Atom::Token(b.clone()), Atom::Token(b)]
 				.into_iter()
 				.collect::<Concatenation>()
 				.into(),
@@ -88,3 +88,26 @@ fn many_matches_unanchored() {
 		assert_eq!(matches, expected);
 	}
 }
+
+#[test]
+fn literal_compilation() {
+	let a = Atom::Token(['a'].into_iter().collect());
+	let b = Atom::Token(['b'].into_iter().collect());
+	let ab: Alternation = [a, b].into_iter().collect::<Concatenation>().into();
+
+	let ire = IRegEx::unanchored(ab.clone());
+	let aut = ire.compile(U32StateBuilder::default()).unwrap();
+	assert!(matches!(aut.root, CompiledAutomaton::Literal(_)));
+	assert!(matches!(aut.prefix, CompiledAutomaton::Nfa(_)));
+	let matches: Vec<_> = aut.matches_str("abxab").collect();
+	assert_eq!(matches, [0..2, 3..5]);
+
+	let ire = IRegEx::anchored(ab);
+	let aut = ire.compile(U32StateBuilder::default()).unwrap();
+	assert!(matches!(aut.prefix, CompiledAutomaton::Literal(_)));
+	assert!(matches!(aut.suffix, CompiledAutomaton::Literal(_)));
+	let mut matches = aut.matches_str("ab");
+	assert_eq!(matches.next(), Some(0..2));
+	assert_eq!(matches.next(), None);
+	assert_eq!(aut.matches_str("abab").next(), None);
+}